@@ -0,0 +1,74 @@
+use std::{fs::{self, File, OpenOptions}, io::Write, path::Path, process};
+
+use crate::logging::{error, warning};
+
+/// Replaces `path` with `content` by writing a temporary file next to it and renaming it over the
+/// destination, so readers never observe a truncated or partially written file.
+pub fn write(path: &str, content: &str) -> bool {
+    let dest = Path::new(path);
+    let dir = match dest.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new(".")
+    };
+    let filename = match dest.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => {
+            error!("Invalid destination path {path}");
+            return false;
+        }
+    };
+    let tmp = dir.join(format!(".{filename}.{}.tmp", process::id()));
+
+    let written = match OpenOptions::new().create(true).truncate(true).write(true).open(&tmp) {
+        Ok(mut file) => {
+            file.write_all(content.as_bytes()).is_ok()
+            &&
+            file.sync_all().is_ok()
+        },
+        Err(e) => {
+            error!("Failed to create temporary file {}: {e}", tmp.to_string_lossy());
+            return false;
+        }
+    };
+
+    if !written {
+        error!("Failed to write temporary file {}", tmp.to_string_lossy());
+        let _ = fs::remove_file(&tmp);
+        return false;
+    }
+
+    if !verify(&tmp, content) {
+        error!("Verification of temporary file {} failed", tmp.to_string_lossy());
+        let _ = fs::remove_file(&tmp);
+        return false;
+    }
+
+    if let Ok(metadata) = fs::metadata(dest) {
+        let _ = fs::set_permissions(&tmp, metadata.permissions());
+    }
+
+    if let Err(e) = fs::rename(&tmp, dest) {
+        error!("Failed to rename {} to {path}: {e}", tmp.to_string_lossy());
+        let _ = fs::remove_file(&tmp);
+        return false;
+    }
+
+    // Not every filesystem (pmxcfs included) supports syncing directories
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+
+    if !verify(dest, content) {
+        warning!("Content of {path} differs from what was written");
+        return false;
+    }
+
+    true
+}
+
+fn verify(path: &Path, content: &str) -> bool {
+    match fs::read(path) {
+        Ok(buf) => buf == content.as_bytes(),
+        Err(_) => false
+    }
+}
@@ -0,0 +1,130 @@
+use std::{env, process};
+
+use crate::config::DEFAULT_CONFIG;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Run,
+    Apply,
+    Render,
+    Check
+}
+
+impl Command {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s {
+            "run" => Some(Self::Run),
+            "apply" => Some(Self::Apply),
+            "render" => Some(Self::Render),
+            "check" => Some(Self::Check),
+            _ => None
+        }
+    }
+}
+
+pub struct Cli {
+    command: Command,
+    config: Option<String>,
+    target: Option<String>,
+    dry_run: bool
+}
+
+impl Cli {
+    pub fn from_args() -> Self {
+        let argv: Vec<String> = env::args().collect();
+        let mut cli = Self {
+            command: Command::Run,
+            config: None,
+            target: None,
+            dry_run: false
+        };
+        let mut command: Option<Command> = None;
+
+        let mut i = 1;
+        while i < argv.len() {
+            match argv[i].as_str() {
+                "-h" | "--help" => {
+                    print_usage();
+                    process::exit(0);
+                },
+                "-V" | "--version" => {
+                    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                    process::exit(0);
+                },
+                "-n" | "--dry-run" => {
+                    cli.dry_run = true;
+                },
+                "-c" | "--config" => {
+                    cli.config = Some(value(&argv, &mut i));
+                },
+                "-t" | "--target" => {
+                    cli.target = Some(value(&argv, &mut i));
+                },
+                arg => {
+                    if let Some(path) = arg.strip_prefix("--config=") {
+                        cli.config = Some(path.to_string());
+                    }
+                    else if let Some(name) = arg.strip_prefix("--target=") {
+                        cli.target = Some(name.to_string());
+                    }
+                    else if let (None, Some(c)) = (command, Command::from_string(arg)) {
+                        command = Some(c);
+                    }
+                    else {
+                        eprintln!("Unknown argument: {arg}");
+                        print_usage();
+                        process::exit(2);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        if let Some(command) = command {
+            cli.command = command;
+        }
+        cli
+    }
+
+    pub fn get_command(&self) -> Command {
+        self.command
+    }
+
+    pub fn get_config(&self) -> Option<&str> {
+        self.config.as_deref()
+    }
+
+    pub fn get_target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+}
+
+fn value(argv: &[String], i: &mut usize) -> String {
+    *i += 1;
+    if *i >= argv.len() {
+        eprintln!("Missing value for {}", argv[*i - 1]);
+        process::exit(2);
+    }
+    argv[*i].clone()
+}
+
+fn print_usage() {
+    println!("Usage: {} [OPTIONS] [COMMAND]
+
+Commands:
+  run     Keep resolving domains and updating the targets until stopped (default)
+  apply   Resolve all domains once, write the targets and exit
+  render  Resolve all domains once and print the generated content to stdout
+  check   Validate all .group and .domains files without resolving anything
+
+Options:
+  -c, --config <PATH>  Configuration file (default: {DEFAULT_CONFIG})
+  -t, --target <NAME>  Only handle the target with this name (apply, render)
+  -n, --dry-run        Print a diff of every target instead of writing it (run, apply)
+  -h, --help           Print this help and exit
+  -V, --version        Print version and exit", env!("CARGO_PKG_NAME"));
+}
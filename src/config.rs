@@ -1,62 +1,462 @@
-use std::env;
+use std::{fs, net::SocketAddr, path::Path, time::Duration};
+
+use toml::{Table, Value};
+
+use crate::{domain::resolver::{self, Protocol, ResolverKind}, logging::Level, target::TargetKind};
+
+pub const DEFAULT_CONFIG: &str = "/etc/pve-dynamic-ipsets/config.toml";
+const DEFAULT_DIRECTORY: &str = "/opt/pve-dynamic-ipsets/";
+const DEFAULT_TARGET: &str = "/etc/pve/firewall/cluster.fw";
 
 #[derive(Debug, Clone)]
 pub struct Config {
+    source: Option<String>,
     directory: String,
+    targets: Vec<TargetConfig>,
+    poll_interval: Duration,
+    dns: DnsConfig,
+    ipsets: IpsetConfig,
+    nftables: NftConfig,
+    log_level: Level
+}
+
+#[derive(Debug, Clone)]
+pub struct TargetConfig {
     file: String,
-    filename: String
+    name: String,
+    kind: Option<TargetKind>,
+    groups: Option<Vec<String>>,
+    domains: Option<Vec<String>>
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsConfig {
+    resolver: ResolverKind,
+    servers: Vec<SocketAddr>,
+    protocol: Protocol,
+    timeout: Duration,
+    retries: u32,
+    min_interval: Duration,
+    default_interval: Duration,
+    retention: Duration,
+    max_addresses: usize
 }
 
 impl Config {
-    pub fn from_args() -> Self {
-        let argv: Vec<String> = env::args().collect();
-        let args: usize = argv.len();
-
-        let directory = {
-            if args > 1 {
-                let d = argv[1].clone();
-                if d.as_bytes()[d.len()-1] != b'/' {
-                    d + "/"
+    /// Loads the given configuration file, or the default one if it exists, falling back to
+    /// built-in defaults otherwise.
+    pub fn find(path: Option<&str>) -> Result<Self, String> {
+        match path {
+            Some(path) => Self::load(path),
+            None => {
+                if Path::new(DEFAULT_CONFIG).exists() {
+                    Self::load(DEFAULT_CONFIG)
                 }
                 else {
-                    d
+                    Ok(Self::default())
                 }
             }
-            else {
-                "/opt/pve-dynamic-ipsets/".to_string()
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {path}: {e}"))?;
+        let mut config = Self::parse(&content).map_err(|e| format!("{path}: {e}"))?;
+        config.source = Some(path.to_string());
+        Ok(config)
+    }
+
+    /// Loads the configuration again from the file it was originally read from.
+    pub fn reload(&self) -> Result<Self, String> {
+        Self::find(self.source.as_deref())
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let document = content.parse::<Table>().map_err(|e| e.to_string().trim_end().to_string())?;
+        let mut config = Self::default();
+        config.targets.clear();
+
+        for (section, value) in &document {
+            if section == "target" {
+                let targets = value.as_array()
+                    .ok_or("target must be an array of tables, written [[target]]")?;
+                for target in targets {
+                    let target = target.as_table()
+                        .ok_or("target must be an array of tables, written [[target]]")?;
+                    let mut target_config = TargetConfig::new(String::new());
+                    for (key, value) in target {
+                        target_config.set(key, value)?;
+                    }
+                    config.targets.push(target_config);
+                }
+                continue;
+            }
+
+            let table = value.as_table().ok_or(format!("{section} must be a table, written [{section}]"))?;
+            for (key, value) in table {
+                config.set(section, key, value)?;
+            }
+        }
+
+        if config.dns.resolver == ResolverKind::System && !config.dns.servers.is_empty() {
+            return Err("dns servers are only used with resolver = \"native\"".to_string());
+        }
+
+        if config.targets.is_empty() {
+            config.targets.push(TargetConfig::new(DEFAULT_TARGET.to_string()));
+        }
+
+        let mut names: Vec<String> = Vec::new();
+        for target in &mut config.targets {
+            if target.file.is_empty() {
+                return Err("target without file".to_string());
+            }
+            if target.kind.is_none() {
+                target.kind = Some(TargetKind::detect(&target.file)
+                    .ok_or(format!("cannot detect kind of target {}, set kind explicitly", target.file))?);
             }
-        };
-        let file = {
-            if args > 2 {
-                argv[2].clone()
+            if target.name.is_empty() {
+                target.name = target.get_kind().default_name(&target.file);
             }
-            else {
-                "/etc/pve/firewall/cluster.fw".to_string()
+            if names.contains(&target.name) {
+                return Err(format!("duplicate target name {}", target.name));
             }
-        };
+            names.push(target.name.clone());
+        }
 
-        let filename = {
-            let parts: Vec<String> = file.split('/').map(|x|x.to_string()).collect();
-            parts[parts.len() - 1].clone()
-        };
+        Ok(config)
+    }
 
+    fn set(&mut self, section: &str, key: &str, value: &Value) -> Result<(), String> {
+        let invalid = || format!("invalid value for {key} in [{section}]");
+        match (section, key) {
+            ("paths", "directory") => {
+                let d = value.as_str().ok_or_else(invalid)?;
+                self.directory = if d.ends_with('/') {d.to_string()} else {format!("{d}/")};
+            },
+            ("daemon", "poll_interval") => {
+                self.poll_interval = Duration::from_secs(integer(value).filter(|i| *i > 0).ok_or_else(invalid)?);
+            },
+            ("dns", "resolver") => {
+                self.dns.resolver = value.as_str().and_then(ResolverKind::from_string).ok_or_else(invalid)?;
+            },
+            ("dns", "servers") => {
+                self.dns.servers = string_list(value)
+                    .and_then(|servers| servers.iter().map(|s| resolver::parse_server(s)).collect())
+                    .ok_or_else(invalid)?;
+            },
+            ("dns", "protocol") => {
+                self.dns.protocol = value.as_str().and_then(Protocol::from_string).ok_or_else(invalid)?;
+            },
+            ("dns", "timeout") => {
+                self.dns.timeout = Duration::from_secs(integer(value).filter(|t| *t > 0).ok_or_else(invalid)?);
+            },
+            ("dns", "retries") => {
+                self.dns.retries = integer(value).and_then(|r| u32::try_from(r).ok()).ok_or_else(invalid)?;
+            },
+            ("dns", "default_interval") => {
+                self.dns.default_interval = minutes(value).ok_or_else(invalid)?;
+            },
+            ("dns", "min_interval") => {
+                self.dns.min_interval = minutes(value).ok_or_else(invalid)?;
+            },
+            ("dns", "retention") => {
+                self.dns.retention = minutes(value).ok_or_else(invalid)?;
+            },
+            ("dns", "max_addresses") => {
+                self.dns.max_addresses = integer(value).and_then(|m| usize::try_from(m).ok()).ok_or_else(invalid)?;
+            },
+            ("ipsets", "collapse") => {
+                self.ipsets.collapse = value.as_bool().ok_or_else(invalid)?;
+            },
+            ("ipsets", "max_widening") => {
+                self.ipsets.max_widening = integer(value).filter(|w| *w <= 128).ok_or_else(invalid)? as u8;
+            },
+            ("nftables", "script") => {
+                self.nftables.script = Some(value.as_str().filter(|s| !s.is_empty()).ok_or_else(invalid)?.to_string());
+            },
+            ("nftables", "table") => {
+                self.nftables.table = value.as_str().filter(|s| !s.is_empty()).ok_or_else(invalid)?.to_string();
+            },
+            ("nftables", "set_format") => {
+                self.nftables.set_format = value.as_str().filter(|s| s.contains("{name}") && s.contains("{family}")).ok_or_else(invalid)?.to_string();
+            },
+            ("nftables", "apply") => {
+                self.nftables.apply = value.as_bool().ok_or_else(invalid)?;
+            },
+            ("nftables", "update_targets") => {
+                self.nftables.update_targets = value.as_bool().ok_or_else(invalid)?;
+            },
+            ("logging", "level") => {
+                self.log_level = value.as_str().and_then(Level::from_string).ok_or_else(invalid)?;
+            },
+            ("paths" | "daemon" | "dns" | "ipsets" | "nftables" | "logging", _) => return Err(format!("unknown key {key} in [{section}]")),
+            _ => return Err(format!("unknown section [{section}]"))
+        }
+        Ok(())
+    }
+
+    pub fn get_directory(&self) -> String {
+        self.directory.clone()
+    }
+
+    pub fn get_targets(&self) -> &Vec<TargetConfig> {
+        &self.targets
+    }
+
+    pub fn get_poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+
+    pub fn get_dns(&self) -> &DnsConfig {
+        &self.dns
+    }
+
+    pub fn get_ipsets(&self) -> &IpsetConfig {
+        &self.ipsets
+    }
+
+    pub fn get_nftables(&self) -> &NftConfig {
+        &self.nftables
+    }
+
+    pub fn get_log_level(&self) -> Level {
+        self.log_level
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut target = TargetConfig::new(DEFAULT_TARGET.to_string());
+        target.kind = Some(TargetKind::Cluster);
+        target.name = TargetKind::Cluster.default_name(DEFAULT_TARGET);
+
+        Self {
+            source: None,
+            directory: DEFAULT_DIRECTORY.to_string(),
+            targets: vec![target],
+            poll_interval: Duration::from_secs(15),
+            dns: DnsConfig::default(),
+            ipsets: IpsetConfig::default(),
+            nftables: NftConfig::default(),
+            log_level: Level::Info
+        }
+    }
+}
+
+impl TargetConfig {
+    fn new(file: String) -> Self {
         Self {
-            directory,
             file,
-            filename
+            name: String::new(),
+            kind: None,
+            groups: None,
+            domains: None
         }
     }
 
-    pub fn get_path(&self, path: ProgramPath) -> String {
-        match path {
-            ProgramPath::Original => {self.file.clone()},
-            ProgramPath::Generated => {
-                self.directory.clone() + "generated/" + self.filename.as_str()
+    fn set(&mut self, key: &str, value: &Value) -> Result<(), String> {
+        let invalid = || format!("invalid value for {key} in [[target]]");
+        match key {
+            "file" => {
+                self.file = value.as_str().ok_or_else(invalid)?.to_string();
             },
-            ProgramPath::Directory => {self.directory.clone()}
-            ProgramPath::Static => {
-                self.directory.clone() + "static/" + self.filename.as_str()
-            }
+            "name" => {
+                self.name = value.as_str().ok_or_else(invalid)?.to_string();
+            },
+            "kind" => {
+                self.kind = Some(value.as_str().and_then(TargetKind::from_string).ok_or_else(invalid)?);
+            },
+            "groups" => {
+                self.groups = Some(string_list(value).ok_or_else(invalid)?);
+            },
+            "domains" => {
+                self.domains = Some(string_list(value).ok_or_else(invalid)?);
+            },
+            _ => return Err(format!("unknown key {key} in [[target]]"))
+        }
+        Ok(())
+    }
+
+    pub fn get_file(&self) -> String {
+        self.file.clone()
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_kind(&self) -> TargetKind {
+        self.kind.clone().unwrap_or(TargetKind::Cluster)
+    }
+
+    /// Names of the `.group` files rendered into this target, `None` meaning all of them.
+    pub fn get_groups(&self) -> Option<&Vec<String>> {
+        self.groups.as_ref()
+    }
+
+    /// Names of the `.domains` files rendered into this target, `None` meaning all of them.
+    pub fn get_domains(&self) -> Option<&Vec<String>> {
+        self.domains.as_ref()
+    }
+
+    pub fn uses_group(&self, name: &String) -> bool {
+        match &self.groups {
+            Some(groups) => groups.contains(name),
+            None => self.get_kind() == TargetKind::Cluster
+        }
+    }
+
+    pub fn uses_domain_file(&self, name: &String) -> bool {
+        match &self.domains {
+            Some(domains) => domains.contains(name),
+            None => self.get_kind() == TargetKind::Cluster
+        }
+    }
+}
+
+impl DnsConfig {
+    /// Resolver used for all domains. The system one by default, so `/etc/hosts`, search domains
+    /// and nsswitch keep applying unless the TTL-aware native one is chosen explicitly.
+    pub fn get_resolver(&self) -> ResolverKind {
+        self.resolver
+    }
+
+    /// Upstream servers of the native resolver, empty meaning those of `/etc/resolv.conf`.
+    pub fn get_servers(&self) -> &Vec<SocketAddr> {
+        &self.servers
+    }
+
+    pub fn get_protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn get_retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Lower bound for the time between refreshes of a domain, regardless of TTLs and intervals.
+    pub fn get_min_interval(&self) -> Duration {
+        self.min_interval
+    }
+
+    /// Upper bound for the time between refreshes of domains declared without an interval.
+    pub fn get_default_interval(&self) -> Duration {
+        self.default_interval
+    }
+
+    /// Time an address stays in its IPSets after it was last returned by DNS, zero dropping it
+    /// on the first answer without it.
+    pub fn get_retention(&self) -> Duration {
+        self.retention
+    }
+
+    /// Upper bound for the addresses kept per domain, retained ones being dropped oldest first.
+    /// Zero means no limit.
+    pub fn get_max_addresses(&self) -> usize {
+        self.max_addresses
+    }
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            resolver: ResolverKind::System,
+            servers: Vec::new(),
+            protocol: Protocol::Udp,
+            timeout: Duration::from_secs(2),
+            retries: 1,
+            min_interval: Duration::from_secs(60),
+            default_interval: Duration::from_secs(60 * 60),
+            retention: Duration::ZERO,
+            max_addresses: 0
+        }
+    }
+}
+
+/// How the entries of rendered IPSets are post-processed.
+#[derive(Debug, Clone)]
+pub struct IpsetConfig {
+    collapse: bool,
+    max_widening: u8
+}
+
+impl IpsetConfig {
+    /// Whether overlapping entries are deduplicated and adjacent ones collapsed into covering networks.
+    pub fn get_collapse(&self) -> bool {
+        self.collapse
+    }
+
+    /// Number of prefix bits a collapsed entry may be widened by beyond a single address, e.g. 8
+    /// allowing IPv4 host entries to be collapsed up to a /24.
+    pub fn get_max_widening(&self) -> u8 {
+        self.max_widening
+    }
+}
+
+impl Default for IpsetConfig {
+    fn default() -> Self {
+        Self {
+            collapse: false,
+            max_widening: 8
+        }
+    }
+}
+
+/// Optional backend writing the addresses of cluster-wide IPSets straight into the kernel sets
+/// the PVE nftables firewall creates for them, so changes apply without a firewall compile.
+#[derive(Debug, Clone)]
+pub struct NftConfig {
+    script: Option<String>,
+    table: String,
+    set_format: String,
+    apply: bool,
+    update_targets: bool
+}
+
+impl NftConfig {
+    /// Path of the `nft -f` batch script, the backend being disabled without one.
+    pub fn get_script(&self) -> Option<&String> {
+        self.script.as_ref()
+    }
+
+    /// Family and name of the table holding the sets, e.g. `inet proxmox-firewall`.
+    pub fn get_table(&self) -> &str {
+        &self.table
+    }
+
+    /// Name of the kernel set for an IPSet, `{family}` being replaced by 4 or 6 and `{name}` by
+    /// the IPSet name. Both are required, otherwise the sets of one family would overwrite those
+    /// of the other.
+    pub fn get_set_name(&self, family: u8, name: &str) -> String {
+        self.set_format.replace("{family}", &family.to_string()).replace("{name}", name)
+    }
+
+    /// Whether the script is run through `nft -f` after writing it.
+    pub fn get_apply(&self) -> bool {
+        self.apply
+    }
+
+    /// Whether address changes still regenerate the firewall files, keeping them in sync with
+    /// the kernel sets for the next firewall compile.
+    pub fn get_update_targets(&self) -> bool {
+        self.update_targets
+    }
+}
+
+impl Default for NftConfig {
+    fn default() -> Self {
+        Self {
+            script: None,
+            table: "inet proxmox-firewall".to_string(),
+            set_format: "v{family}-dc/{name}".to_string(),
+            apply: true,
+            update_targets: true
         }
     }
 }
@@ -64,6 +464,54 @@ impl Config {
 pub enum ProgramPath {
     Original,
     Generated,
-    Directory,
     Static
-}
\ No newline at end of file
+}
+
+fn integer(value: &Value) -> Option<u64> {
+    value.as_integer().and_then(|i| u64::try_from(i).ok())
+}
+
+fn minutes(value: &Value) -> Option<Duration> {
+    integer(value)?.checked_mul(60).map(Duration::from_secs)
+}
+
+fn string_list(value: &Value) -> Option<Vec<String>> {
+    value.as_array()?.iter().map(|v| v.as_str().map(str::to_string)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_multi_line_arrays_and_strings() {
+        let config = Config::parse(r#"
+[dns]
+resolver = "native"
+servers = [
+    "1.1.1.1", # primary
+    '[2606:4700::1111]:53',
+]
+
+[[target]]
+file = "/etc/pve/firewall/cluster.fw"
+groups = ["a,b", "c\"d", "\u00e9"]
+"#).unwrap();
+        assert_eq!(config.get_dns().get_servers().len(), 2);
+        assert_eq!(config.get_targets()[0].get_groups().unwrap(), &["a,b", "c\"d", "\u{e9}"]);
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let error = |content: &str| Config::parse(content).err().unwrap();
+        assert_eq!(error("[target]\nfile = \"x\""), "target must be an array of tables, written [[target]]");
+        assert_eq!(error("[daemon]\npoll_interval = 0"), "invalid value for poll_interval in [daemon]");
+        assert_eq!(error("[dns]\ntimeout = 1.5"), "invalid value for timeout in [dns]");
+        assert_eq!(error("[dns]\ndefault_interval = 999999999999999999"), "invalid value for default_interval in [dns]");
+        assert_eq!(error("[dns]\nretries = 4294967296"), "invalid value for retries in [dns]");
+        assert_eq!(error("[dns]\nmax_addresses = -1"), "invalid value for max_addresses in [dns]");
+        assert_eq!(error("[dns]\nport = 53"), "unknown key port in [dns]");
+        assert_eq!(error("[nftables]\nset_format = \"dc/{name}\""), "invalid value for set_format in [nftables]");
+        assert!(Config::parse("[paths]\ndirectory = \"x\"\n[paths]").is_err());
+    }
+}
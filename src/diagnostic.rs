@@ -0,0 +1,63 @@
+use std::fmt;
+
+/// Problem found while parsing a single line, located by its 1-based column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    column: usize,
+    message: String
+}
+
+impl ParseError {
+    pub fn new(column: usize, message: String) -> Self {
+        Self {
+            column,
+            message
+        }
+    }
+
+    pub fn locate(self, path: &str, line: usize) -> Diagnostic {
+        Diagnostic {
+            path: path.to_string(),
+            line,
+            column: self.column,
+            message: self.message
+        }
+    }
+}
+
+/// Problem found in a configuration file, printed as `path:line:column: message`.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    path: String,
+    line: usize,
+    column: usize,
+    message: String
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.path, self.line, self.column, self.message)
+    }
+}
+
+/// Splits a line on whitespace, keeping the 1-based column each token starts at.
+pub fn tokenize(s: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut start: Option<usize> = None;
+    for (i, c) in s.char_indices() {
+        if c.is_whitespace() {
+            if let Some(st) = start.take() {
+                tokens.push((st, &s[st..i]));
+            }
+        }
+        else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(st) = start {
+        tokens.push((st, &s[st..]));
+    }
+    tokens.into_iter()
+        .map(|(i, t)| (s[..i].chars().count() + 1, t))
+        .collect()
+}
@@ -0,0 +1,216 @@
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert
+}
+
+struct Edit {
+    op: Op,
+    old: usize,
+    new: usize
+}
+
+/// Renders a unified diff between `old` and `new`, returning an empty string if both are equal.
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let edits = edit_script(&a, &b);
+
+    let changes: Vec<usize> = edits.iter().enumerate()
+        .filter(|(_, e)| e.op != Op::Equal)
+        .map(|(i, _)| i)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut buf = format!("--- {old_name}\n+++ {new_name}\n");
+
+    let mut i = 0;
+    while i < changes.len() {
+        let start = changes[i].saturating_sub(CONTEXT);
+        let mut end = changes[i];
+        while i < changes.len() && changes[i] <= end + 2 * CONTEXT + 1 {
+            end = changes[i];
+            i += 1;
+        }
+        let end = (end + CONTEXT + 1).min(edits.len());
+        let hunk = &edits[start..end];
+
+        let old_len = hunk.iter().filter(|e| e.op != Op::Insert).count();
+        let new_len = hunk.iter().filter(|e| e.op != Op::Delete).count();
+        let old_start = if old_len > 0 {hunk[0].old + 1} else {hunk[0].old};
+        let new_start = if new_len > 0 {hunk[0].new + 1} else {hunk[0].new};
+        buf += format!("@@ -{old_start},{old_len} +{new_start},{new_len} @@\n").as_str();
+
+        for edit in hunk {
+            match edit.op {
+                Op::Equal => buf += format!(" {}\n", a[edit.old]).as_str(),
+                Op::Delete => buf += format!("-{}\n", a[edit.old]).as_str(),
+                Op::Insert => buf += format!("+{}\n", b[edit.new]).as_str()
+            }
+        }
+    }
+
+    buf
+}
+
+/// Shortest edit script between two line sequences using the linear space variant of Myers'
+/// algorithm, so memory only grows with the input length.
+fn edit_script(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let mut edits: Vec<Edit> = Vec::new();
+    diff_range(a, b, 0, 0, &mut edits);
+    edits
+}
+
+/// Appends the edits turning `a` into `b`, which start at lines `x0` and `y0` of the full inputs.
+fn diff_range(a: &[&str], b: &[&str], x0: usize, y0: usize, edits: &mut Vec<Edit>) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    for i in 0..prefix {
+        edits.push(Edit {op: Op::Equal, old: x0 + i, new: y0 + i});
+    }
+    let (a, b, x0, y0) = (&a[prefix..], &b[prefix..], x0 + prefix, y0 + prefix);
+
+    let suffix = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+
+    if a.is_empty() {
+        for i in 0..b.len() {
+            edits.push(Edit {op: Op::Insert, old: x0, new: y0 + i});
+        }
+    }
+    else if b.is_empty() {
+        for i in 0..a.len() {
+            edits.push(Edit {op: Op::Delete, old: x0 + i, new: y0});
+        }
+    }
+    else {
+        let (x, y, u, v) = middle_snake(a, b);
+        diff_range(&a[..x], &b[..y], x0, y0, edits);
+        for i in 0..u - x {
+            edits.push(Edit {op: Op::Equal, old: x0 + x + i, new: y0 + y + i});
+        }
+        diff_range(&a[u..], &b[v..], x0 + u, y0 + v, edits);
+    }
+
+    for i in 0..suffix {
+        edits.push(Edit {op: Op::Equal, old: x0 + a.len() + i, new: y0 + b.len() + i});
+    }
+}
+
+/// Middle snake of a shortest edit script between two non-empty sequences, found by searching
+/// forward from the start and backward from the end until both meet. Returns its start and end
+/// as `(x, y, u, v)`.
+fn middle_snake(a: &[&str], b: &[&str]) -> (usize, usize, usize, usize) {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    // Furthest x reached on each diagonal, backward ones counted from the end of both inputs.
+    let mut forward = vec![0isize; 2 * offset as usize + 1];
+    let mut backward = vec![0isize; 2 * offset as usize + 1];
+
+    for d in 0..=max {
+        let mut k = -d;
+        while k <= d {
+            let idx = (k + offset) as usize;
+            let start = if k == -d || (k != d && forward[idx - 1] < forward[idx + 1]) {
+                forward[idx + 1]
+            }
+            else {
+                forward[idx - 1] + 1
+            };
+            let mut x = start;
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[idx] = x;
+            let c = delta - k;
+            if odd && c.abs() < d && x + backward[(c + offset) as usize] >= n {
+                return (start as usize, (start - k) as usize, x as usize, y as usize);
+            }
+            k += 2;
+        }
+
+        let mut c = -d;
+        while c <= d {
+            let idx = (c + offset) as usize;
+            let start = if c == -d || (c != d && backward[idx - 1] < backward[idx + 1]) {
+                backward[idx + 1]
+            }
+            else {
+                backward[idx - 1] + 1
+            };
+            let mut x = start;
+            let mut y = x - c;
+            while x < n && y < m && a[(n - 1 - x) as usize] == b[(m - 1 - y) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[idx] = x;
+            let k = delta - c;
+            if !odd && k.abs() <= d && x + forward[(k + offset) as usize] >= n {
+                return ((n - x) as usize, (m - y) as usize, (n - start) as usize, (m - start + c) as usize);
+            }
+            c += 2;
+        }
+    }
+    unreachable!("sequences always meet within half their combined length")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Length of the longest common subsequence, by dynamic programming.
+    fn lcs(a: &[&str], b: &[&str]) -> usize {
+        let mut table = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i][j] = if a[i] == b[j] {table[i + 1][j + 1] + 1} else {table[i + 1][j].max(table[i][j + 1])};
+            }
+        }
+        table[0][0]
+    }
+
+    #[test]
+    fn edit_script_is_shortest() {
+        let words = ["a", "b", "c", "d"];
+        let mut seed = 0x2545f4914f6cdd1du64;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for round in 0..5000 {
+            let size = if round % 100 == 0 {200} else {12};
+            let a: Vec<&str> = (0..next() % size).map(|_| words[(next() % 4) as usize]).collect();
+            let b: Vec<&str> = (0..next() % size).map(|_| words[(next() % 4) as usize]).collect();
+            let edits = edit_script(&a, &b);
+
+            let old: Vec<&str> = edits.iter().filter(|e| e.op != Op::Insert).map(|e| a[e.old]).collect();
+            let new: Vec<&str> = edits.iter().filter(|e| e.op != Op::Delete).map(|e| b[e.new]).collect();
+            assert_eq!(old, a);
+            assert_eq!(new, b);
+            assert!(edits.iter().filter(|e| e.op == Op::Equal).all(|e| a[e.old] == b[e.new]));
+            assert_eq!(edits.iter().filter(|e| e.op == Op::Equal).count(), lcs(&a, &b));
+        }
+    }
+
+    #[test]
+    fn unified_hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n11\n";
+        assert_eq!(unified(old, old, "a", "b"), "");
+        assert_eq!(unified(old, new, "a", "b"),
+            "--- a\n+++ b\n@@ -2,9 +2,10 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n 9\n 10\n+11\n");
+    }
+}
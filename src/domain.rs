@@ -1,32 +1,196 @@
-use std::{net::IpAddr, time::{Duration, Instant}};
+use std::{net::IpAddr, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
 use dns_lookup::lookup_host;
+use resolver::ResolverKind;
+
+use crate::{config::{DnsConfig, IpsetConfig}, diagnostic::{tokenize, ParseError}, logging::{debug, warning}, network::{Entry, IpSet, Network}};
+
+pub mod resolver;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Family {
+    V4,
+    V6,
+    Any
+}
+
+impl Family {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "v4" | "ipv4" | "inet" | "4" => Some(Self::V4),
+            "v6" | "ipv6" | "inet6" | "6" => Some(Self::V6),
+            "any" | "both" | "dual" => Some(Self::Any),
+            _ => None
+        }
+    }
+
+    pub fn includes(&self, other: Family) -> bool {
+        *self == Self::Any || *self == other
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(_) => self.includes(Self::V4),
+            IpAddr::V6(_) => self.includes(Self::V6)
+        }
+    }
+
+    pub fn union(&self, other: Family) -> Self {
+        if *self == other {*self} else {Self::Any}
+    }
+}
+
+/// Resolved address of a domain, with the CNAME records it was reached through and the time it
+/// was last returned by DNS.
+#[derive(Debug, Clone)]
+pub struct Address {
+    ip: IpAddr,
+    chain: Vec<String>,
+    last_seen: SystemTime
+}
+
+impl Address {
+    pub fn new(ip: IpAddr, chain: Vec<String>) -> Self {
+        Self {
+            ip,
+            chain,
+            last_seen: SystemTime::now()
+        }
+    }
+
+    pub fn get_ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// Canonical names followed from the domain to this address, empty for a direct record.
+    pub fn get_chain(&self) -> &Vec<String> {
+        &self.chain
+    }
+
+    pub fn get_last_seen(&self) -> SystemTime {
+        self.last_seen
+    }
+
+    /// Whether both resolve to the same address the same way, regardless of when.
+    fn same_as(&self, other: &Address) -> bool {
+        self.ip == other.ip && self.chain == other.chain
+    }
+}
 
 pub struct Domain {
     fqdn: String,
-    interval: Duration,
+    interval: Option<Duration>,
+    family: Option<Family>,
+    split: Option<bool>,
+    queries: Option<u32>,
+    all_resolvers: Option<bool>,
+    window: Option<Duration>,
+    ttl: Option<Duration>,
     last_refresh: Option<Instant>,
-    ips: Vec<IpAddr>
+    addresses: Vec<Address>
 }
 
 impl Domain {
-    pub fn from_string(s: &String) -> Option<Self> {
-        let parts: Vec<String> = s.split(' ').map(|x|x.to_string()).collect();
-        if parts.len() == 2 {
-            if let Ok(minutes) = u64::from_str_radix(&parts[1], 10) {
-                let mut domain = Self {
-                    fqdn: parts[0].clone(),
-                    interval: Duration::from_secs(minutes * 60),
-                    last_refresh: None,
-                    ips: Vec::new()
-                };
-
-                domain.update();
-
-                return Some(domain);
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(s);
+        if tokens.is_empty() {
+            return Err(ParseError::new(1, "expected domain".to_string()));
+        }
+
+        let (column, fqdn) = tokens[0];
+        validate_fqdn(fqdn).map_err(|e| ParseError::new(column, e))?;
+
+        let mut domain = Self {
+            fqdn: fqdn.to_string(),
+            interval: None,
+            family: None,
+            split: None,
+            queries: None,
+            all_resolvers: None,
+            window: None,
+            ttl: None,
+            last_refresh: None,
+            addresses: Vec::new()
+        };
+
+        for (i, (column, token)) in tokens.iter().enumerate().skip(1) {
+            let (key, value) = match token.split_once('=') {
+                Some(kv) => kv,
+                None if i == 1 => {
+                    match parse_minutes(token) {
+                        Some(interval) => domain.interval = Some(interval),
+                        None => return Err(ParseError::new(*column, format!("interval {token} is not a number of minutes")))
+                    }
+                    continue;
+                },
+                None => return Err(ParseError::new(*column, format!("expected key=value option, found {token}")))
+            };
+            match key {
+                "family" => {
+                    domain.family = Some(Family::from_string(value)
+                        .ok_or(ParseError::new(*column, format!("unknown address family {value}")))?);
+                },
+                "split" => {
+                    domain.split = Some(parse_bool(value)
+                        .ok_or(ParseError::new(*column, format!("expected yes or no for split, found {value}")))?);
+                },
+                "queries" => {
+                    domain.queries = Some(value.parse::<u32>().ok().filter(|q| *q > 0)
+                        .ok_or(ParseError::new(*column, format!("expected a positive number of queries, found {value}")))?);
+                },
+                "resolvers" => {
+                    domain.all_resolvers = Some(match value.to_ascii_lowercase().as_str() {
+                        "all" => true,
+                        "first" => false,
+                        _ => return Err(ParseError::new(*column, format!("expected all or first for resolvers, found {value}")))
+                    });
+                },
+                "window" => {
+                    domain.window = Some(parse_minutes(value)
+                        .ok_or(ParseError::new(*column, format!("window {value} is not a number of minutes")))?);
+                },
+                _ => return Err(ParseError::new(*column, format!("unknown option {key}")))
             }
         }
-        None
+
+        Ok(domain)
+    }
+
+    /// Fills options the domain did not set itself from the defaults of the file declaring it.
+    pub fn inherit(&mut self, family: Option<Family>, split: Option<bool>) {
+        self.family = self.family.or(family);
+        self.split = self.split.or(split);
+    }
+
+    /// Combines the options of another declaration of the same domain, resolving often enough and
+    /// broadly enough to satisfy both.
+    pub fn merge(&mut self, other: &Domain) {
+        self.interval = match (self.interval, other.interval) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b)
+        };
+        // Declarations asking for fewer families than the merged domain is resolved for reference
+        // the per-family IPSets, so those have to be rendered.
+        let narrowed = self.get_family() != other.get_family();
+        self.family = Some(self.get_family().union(other.get_family()));
+        self.split = Some(self.get_split() || other.get_split() || narrowed);
+        self.queries = self.queries.max(other.queries);
+        self.all_resolvers = self.all_resolvers.max(other.all_resolvers);
+        self.window = self.window.max(other.window);
+    }
+
+    /// Takes over the options of a freshly loaded declaration while keeping the resolved state,
+    /// unless the address family changed.
+    pub fn adopt(&mut self, other: &Domain) {
+        if self.get_family() != other.get_family() {
+            self.last_refresh = None;
+        }
+        self.interval = other.interval;
+        self.family = other.family;
+        self.split = other.split;
+        self.queries = other.queries;
+        self.all_resolvers = other.all_resolvers;
+        self.window = other.window;
     }
 
     pub fn get_name(&self) -> String {
@@ -37,70 +201,347 @@ impl Domain {
         self.fqdn.clone()
     }
 
-    fn update(&mut self) -> Option<bool> {
-        println!("Updating domain: {}", self.fqdn);
-        match lookup_host(&self.fqdn) {
-            Ok(ips) => {
-                if ips.len() == self.ips.len() {
-                    for ip in &ips {
-                        if !self.ips.contains(ip) {
-                            self.ips = ips;
-                            self.last_refresh = Some(Instant::now());
-                            return Some(true);
-                        }
-                    }
-                    self.last_refresh = Some(Instant::now());
-                    return Some(false);
+    pub fn update(&mut self, dns: &DnsConfig) -> Option<bool> {
+        debug!("Updating domain: {}", self.fqdn);
+        let family = self.get_family();
+        let result = match dns.get_resolver() {
+            ResolverKind::Native => resolver::collect(&self.fqdn, family, dns, self.get_queries(), self.all_resolvers.unwrap_or(false))
+                .map(|answer| (answer.get_addresses().clone(), Some(answer.get_ttl()))),
+            ResolverKind::System => self.lookup_system(family).map(|addresses| (addresses, None))
+        };
+        match result {
+            Ok((addresses, ttl)) => {
+                for address in addresses.iter().filter(|a| !a.get_chain().is_empty()) {
+                    debug!("{} resolved to {} via {}", self.fqdn, address.get_ip(), address.get_chain().join(" -> "));
                 }
-
-                self.ips = ips;
+                self.ttl = ttl;
                 self.last_refresh = Some(Instant::now());
-                Some(true)
+                let addresses = self.retain(addresses, dns);
+                // Only a different set of addresses counts as a change, so a refresh merely
+                // renewing the last-seen times does not rewrite the firewall configuration.
+                let changed = addresses.len() != self.addresses.len()
+                    || addresses.iter().zip(&self.addresses).any(|(a, b)| !a.same_as(b));
+                self.addresses = addresses;
+                Some(changed)
             }
             Err(e) => {
-                println!("Name resolve for {} failed. Keeping old config for this host. Error: {e}", &self.fqdn);
+                warning!("Name resolve for {} failed. Keeping old config for this host. Error: {e}", &self.fqdn);
                 None
             }
         }
     }
 
-    pub fn try_update(&mut self) -> Option<bool> {
-        if self.last_refresh.is_none() {
-            return self.update();
+    /// Resolves through libc `queries` times, unioning the answers. Which resolvers are asked is
+    /// up to the system configuration.
+    fn lookup_system(&self, family: Family) -> Result<Vec<Address>, String> {
+        let mut addresses: Vec<Address> = Vec::new();
+        let mut result: Result<(), String> = Err(String::new());
+        for _ in 0..self.get_queries() {
+            match lookup_host(&self.fqdn) {
+                Ok(ips) => {
+                    addresses.extend(ips.into_iter()
+                        .filter(|ip| family.contains(ip))
+                        .map(|ip| Address::new(ip, Vec::new())));
+                    result = Ok(());
+                },
+                Err(e) if result.is_err() => result = Err(e.to_string()),
+                Err(_) => {}
+            }
         }
-        if self.last_refresh.unwrap().elapsed() >= self.interval {
-            return self.update();
+        addresses.sort_by_key(|a| a.get_ip());
+        addresses.dedup_by_key(|a| a.get_ip());
+        result.map(|_| addresses)
+    }
+
+    /// Adds the previously known addresses of the domain's family missing from a fresh answer that
+    /// were seen within the domain's window, or the global retention period, then drops the oldest
+    /// of them beyond the address limit. A fresh answer larger than the limit keeps the lowest
+    /// addresses only, so the selection does not change with the order of the records.
+    fn retain(&self, mut addresses: Vec<Address>, dns: &DnsConfig) -> Vec<Address> {
+        let family = self.get_family();
+        let mut retained: Vec<Address> = self.addresses.iter()
+            .filter(|old| family.contains(&old.ip))
+            .filter(|old| !addresses.iter().any(|a| a.ip == old.ip))
+            .filter(|old| old.last_seen.elapsed().is_ok_and(|age| age < self.window.unwrap_or(dns.get_retention())))
+            .cloned()
+            .collect();
+
+        let limit = dns.get_max_addresses();
+        if limit > 0 {
+            if addresses.len() > limit {
+                debug!("{} resolved to {} addresses, keeping the lowest {limit}", self.fqdn, addresses.len());
+                addresses.sort_by_key(|a| a.ip);
+                addresses.truncate(limit);
+            }
+            retained.sort_by_key(|a| std::cmp::Reverse(a.last_seen));
+            retained.truncate(limit.saturating_sub(addresses.len()));
+        }
+        for address in &retained {
+            debug!("Retaining {} for {}, last seen {}", address.ip, self.fqdn, format_timestamp(address.last_seen));
+        }
+
+        addresses.extend(retained);
+        addresses.sort_by_key(|a| a.ip);
+        addresses
+    }
+
+    pub fn try_update(&mut self, dns: &DnsConfig) -> Option<bool> {
+        match self.last_refresh {
+            Some(last_refresh) if last_refresh.elapsed() < self.refresh_after(dns) => Some(false),
+            _ => self.update(dns)
         }
-        Some(false)
     }
 
-    pub fn try_render(&self) -> Option<String> {
-        if self.last_refresh.is_some() && self.ips.len() > 0 {
-            let mut result = format!("[IPSET domain_{}]\n\n", self.get_name());
-            for ip in &self.ips {
-                let suffix = {
-                    if ip.is_ipv4() {"/32"}
-                    else {"/128"}
-                };
-                result += format!("{}{suffix}\n", ip.to_string()).as_str();
+    /// Time between refreshes: the record TTL if known, bounded below by the global minimum
+    /// interval and above by the domain's own interval, falling back to the default interval.
+    fn refresh_after(&self, dns: &DnsConfig) -> Duration {
+        let floor = dns.get_min_interval();
+        let ceiling = self.interval.unwrap_or(dns.get_default_interval()).max(floor);
+        match self.ttl {
+            Some(ttl) => ttl.max(floor).min(ceiling),
+            None => ceiling
+        }
+    }
+
+    /// IPSets of the domain: the combined one plus the per-family ones when split. Empty until
+    /// the domain resolved to at least one address.
+    pub fn get_ipsets(&self, dns: &DnsConfig, config: &IpsetConfig) -> Vec<IpSet> {
+        let mut ipsets: Vec<IpSet> = Vec::new();
+        if !self.verify() {
+            return ipsets;
+        }
+        // The TTL left in caches changes on every refresh, so the comment names the configured
+        // interval to keep the rendered set stable while its addresses are.
+        let interval = self.interval.unwrap_or(dns.get_default_interval()).max(dns.get_min_interval());
+        let comment = format!("{}, refreshed at least every {}", self.fqdn, format_interval(interval));
+        let entries = |family: Family| -> Vec<Entry> {
+            self.addresses.iter()
+                .filter(|a| family.contains(&a.get_ip()))
+                .map(|address| entry(&self.fqdn, address))
+                .collect()
+        };
+        ipsets.push(IpSet::new(self.get_ipset_name(Family::Any), comment.clone(), entries(Family::Any), config));
+        if self.get_split() {
+            for family in [Family::V4, Family::V6] {
+                if self.get_family().includes(family) {
+                    ipsets.push(IpSet::new(self.get_ipset_name(family), comment.clone(), entries(family), config));
+                }
             }
-            result += "\n";
-            return Some(result);
         }
-        None
+        ipsets
     }
 
-    pub fn get_interval(&self) -> Duration {
-        self.interval.clone()
+    /// Name of the IPSet holding the addresses of `family`: the per-family one if the domain is
+    /// resolved for other families as well, otherwise the combined one.
+    pub fn get_ipset_name(&self, family: Family) -> String {
+        match family {
+            Family::V4 if self.get_family() != Family::V4 => format!("domain_{}_v4", self.get_name()),
+            Family::V6 if self.get_family() != Family::V6 => format!("domain_{}_v6", self.get_name()),
+            _ => format!("domain_{}", self.get_name())
+        }
+    }
+
+    pub fn get_addresses(&self) -> &Vec<Address> {
+        &self.addresses
+    }
+
+    pub fn get_family(&self) -> Family {
+        self.family.unwrap_or(Family::Any)
+    }
+
+    /// Number of queries whose answers are unioned on every refresh.
+    pub fn get_queries(&self) -> u32 {
+        self.queries.unwrap_or(1)
     }
 
-    pub fn set_interval(&mut self, interval: Duration) {
-        self.interval = interval;
+    /// Whether separate `_v4` and `_v6` IPSets are rendered next to the combined one.
+    pub fn get_split(&self) -> bool {
+        self.split.unwrap_or(false)
+    }
+
+    /// Marks the domain as resolved to `ips`, all last seen at the epoch.
+    #[cfg(test)]
+    pub fn set_resolved(&mut self, ips: &[&str]) {
+        self.addresses = ips.iter()
+            .map(|ip| Address {
+                ip: ip.parse().unwrap(),
+                chain: Vec::new(),
+                last_seen: UNIX_EPOCH
+            })
+            .collect();
+        self.last_refresh = Some(Instant::now());
     }
 
     pub fn verify(&self) -> bool {
         self.last_refresh.is_some()
         &&
-        self.ips.len() > 0
+        !self.addresses.is_empty()
+    }
+}
+
+/// IPSet entry for an address of `fqdn`, commented with how and when it was resolved.
+pub fn entry(fqdn: &str, address: &Address) -> Entry {
+    let mut names = vec![fqdn.to_string()];
+    names.extend(address.get_chain().iter().cloned());
+    let comment = format!("{}, last seen {}", names.join(" -> "), format_timestamp(address.get_last_seen()));
+    Entry::new(Network::host(address.get_ip()), Some(fqdn.to_string()), Some(comment))
+}
+
+/// Formats an interval in the largest unit dividing it evenly, e.g. `5m`.
+// `is_multiple_of` would require Rust 1.87.
+#[allow(clippy::manual_is_multiple_of)]
+fn format_interval(interval: Duration) -> String {
+    let secs = interval.as_secs();
+    if secs > 0 && secs % 3600 == 0 {
+        format!("{}h", secs / 3600)
+    }
+    else if secs > 0 && secs % 60 == 0 {
+        format!("{}m", secs / 60)
+    }
+    else {
+        format!("{secs}s")
+    }
+}
+
+/// Formats a point in time as an ISO 8601 UTC timestamp, e.g. `2024-05-01T12:00:00Z`.
+fn format_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since 1970-01-01, after Howard Hinnant's days_from_civil inverse.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + if month <= 2 {1} else {0};
+
+    format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z", rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+fn parse_minutes(s: &str) -> Option<Duration> {
+    s.parse::<u64>().ok()?.checked_mul(60).map(Duration::from_secs)
+}
+
+pub fn parse_bool(s: &str) -> Option<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "yes" | "true" | "on" | "1" => Some(true),
+        "no" | "false" | "off" | "0" => Some(false),
+        _ => None
+    }
+}
+
+fn validate_fqdn(fqdn: &str) -> Result<(), String> {
+    if fqdn.len() > 253 {
+        return Err(format!("invalid FQDN {fqdn}: longer than 253 characters"));
+    }
+    for label in fqdn.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid FQDN {fqdn}: labels must be 1 to 63 characters long"));
+        }
+        if !label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') {
+            return Err(format!("invalid FQDN {fqdn}: label {label} contains invalid characters"));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("invalid FQDN {fqdn}: label {label} starts or ends with a hyphen"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, IpsetConfig};
+
+    fn address(ip: &str, chain: &[&str], last_seen: u64) -> Address {
+        Address {
+            ip: ip.parse().unwrap(),
+            chain: chain.iter().map(|c| c.to_string()).collect(),
+            last_seen: UNIX_EPOCH + Duration::from_secs(last_seen)
+        }
     }
-}
\ No newline at end of file
+
+    fn seen(ip: &str, minutes_ago: u64) -> Address {
+        Address {
+            ip: ip.parse().unwrap(),
+            chain: Vec::new(),
+            last_seen: SystemTime::now() - Duration::from_secs(minutes_ago * 60)
+        }
+    }
+
+    fn ips(addresses: &[Address]) -> Vec<String> {
+        addresses.iter().map(|a| a.get_ip().to_string()).collect()
+    }
+
+    #[test]
+    fn retains_recent_addresses_within_window() {
+        let dns = Config::parse("[dns]\nretention = 60").unwrap().get_dns().clone();
+        let mut domain = Domain::parse("a.test window=10").unwrap();
+        domain.addresses = vec![seen("10.0.0.2", 5), seen("10.0.0.3", 15), seen("10.0.0.1", 30)];
+        assert_eq!(ips(&domain.retain(vec![seen("10.0.0.9", 0)], &dns)), ["10.0.0.2", "10.0.0.9"]);
+
+        let mut domain = Domain::parse("a.test").unwrap();
+        domain.addresses = vec![seen("10.0.0.3", 15), seen("10.0.0.1", 90)];
+        assert_eq!(ips(&domain.retain(vec![seen("10.0.0.9", 0)], &dns)), ["10.0.0.3", "10.0.0.9"]);
+    }
+
+    #[test]
+    fn drops_oldest_addresses_beyond_limit() {
+        let dns = Config::parse("[dns]\nretention = 60\nmax_addresses = 3").unwrap().get_dns().clone();
+        let mut domain = Domain::parse("a.test").unwrap();
+        domain.addresses = vec![seen("10.0.0.1", 30), seen("10.0.0.2", 5), seen("10.0.0.3", 15)];
+        assert_eq!(ips(&domain.retain(vec![seen("10.0.0.9", 0)], &dns)), ["10.0.0.2", "10.0.0.3", "10.0.0.9"]);
+
+        let fresh = vec![seen("10.0.0.9", 0), seen("10.0.0.7", 0), seen("10.0.0.8", 0), seen("10.0.0.6", 0)];
+        assert_eq!(ips(&domain.retain(fresh, &dns)), ["10.0.0.6", "10.0.0.7", "10.0.0.8"]);
+    }
+
+    #[test]
+    fn drops_retained_addresses_of_other_family() {
+        let dns = Config::parse("[dns]\nretention = 60").unwrap().get_dns().clone();
+        let mut domain = Domain::parse("a.test family=v4").unwrap();
+        domain.addresses = vec![seen("10.0.0.1", 5), seen("fd00::1", 5)];
+        assert_eq!(ips(&domain.retain(vec![seen("10.0.0.9", 0)], &dns)), ["10.0.0.1", "10.0.0.9"]);
+    }
+
+    #[test]
+    fn refreshes_after_bounded_ttl() {
+        let dns = Config::parse("[dns]\nmin_interval = 5\ndefault_interval = 30").unwrap().get_dns().clone();
+        let mut domain = Domain::parse("a.test 20").unwrap();
+        domain.ttl = Some(Duration::from_secs(60));
+        assert_eq!(domain.refresh_after(&dns), Duration::from_secs(5 * 60));
+        domain.ttl = Some(Duration::from_secs(10 * 60));
+        assert_eq!(domain.refresh_after(&dns), Duration::from_secs(10 * 60));
+        domain.ttl = Some(Duration::from_secs(86400));
+        assert_eq!(domain.refresh_after(&dns), Duration::from_secs(20 * 60));
+        domain.ttl = None;
+        assert_eq!(domain.refresh_after(&dns), Duration::from_secs(20 * 60));
+
+        let mut domain = Domain::parse("a.test").unwrap();
+        assert_eq!(domain.refresh_after(&dns), Duration::from_secs(30 * 60));
+        domain.ttl = Some(Duration::from_secs(86400));
+        assert_eq!(domain.refresh_after(&dns), Duration::from_secs(30 * 60));
+        let domain = Domain::parse("a.test 1").unwrap();
+        assert_eq!(domain.refresh_after(&dns), Duration::from_secs(5 * 60));
+    }
+
+    #[test]
+    fn comments_entries_with_chain_and_last_seen() {
+        let entries = vec![
+            entry("a.test", &address("10.0.0.1", &[], 1714564800)),
+            entry("www.test", &address("fd00::1", &["edge.cdn.test", "node.cdn.test"], 951827696))
+        ];
+        let ipset = IpSet::new("domain_a_test".to_string(), "a.test".to_string(), entries, &IpsetConfig::default());
+        assert_eq!(ipset.render(), "\
+[IPSET domain_a_test] # a.test
+
+10.0.0.1/32 # a.test, last seen 2024-05-01T12:00:00Z
+fd00::1/128 # www.test -> edge.cdn.test -> node.cdn.test, last seen 2000-02-29T12:34:56Z
+
+");
+    }
+}
@@ -0,0 +1,576 @@
+use std::{collections::{hash_map::RandomState, HashMap}, fs::{self, File}, hash::{BuildHasher, Hasher}, io::{Read, Write}, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use super::{Address, Family};
+use crate::{config::DnsConfig, logging::debug};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const MAX_CNAME_CHAIN: usize = 16;
+const MAX_POINTERS: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResolverKind {
+    /// Built-in DNS client, honoring record TTLs
+    Native,
+    /// libc `getaddrinfo`, refreshing on the configured interval only
+    System
+}
+
+impl ResolverKind {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "native" | "dns" => Some(Self::Native),
+            "system" | "libc" => Some(Self::System),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// UDP, repeating the query over TCP if the answer was truncated
+    Udp,
+    Tcp
+}
+
+impl Protocol {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "udp" => Some(Self::Udp),
+            "tcp" => Some(Self::Tcp),
+            _ => None
+        }
+    }
+}
+
+/// Addresses of a name together with the lowest TTL of the records they were reached through.
+pub struct Answer {
+    addresses: Vec<Address>,
+    ttl: Duration
+}
+
+impl Answer {
+    pub fn get_addresses(&self) -> &Vec<Address> {
+        &self.addresses
+    }
+
+    pub fn get_ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+/// Resolves the A and/or AAAA records of `fqdn`, depending on `family`, against the configured upstream servers, or the
+/// nameservers of `/etc/resolv.conf` if none are configured. Servers are tried in order until one
+/// answers, the whole list being retried `retries` times.
+///
+/// This is repeated `queries` times, against every server separately if `all_servers` is set, and
+/// the answers are unioned. This collects more of a pool whose servers only return a rotating
+/// subset of it per query. Fails only if no query succeeded.
+pub fn collect(fqdn: &str, family: Family, dns: &DnsConfig, queries: u32, all_servers: bool) -> Result<Answer, String> {
+    let servers = upstream_servers(dns);
+    let server_lists: Vec<&[SocketAddr]> = if all_servers {
+        servers.chunks(1).collect()
+    }
+    else {
+        vec![&servers[..]]
+    };
+
+    let mut result: Result<Answer, String> = Err(String::from("no query made"));
+    for _ in 0..queries.max(1) {
+        for list in &server_lists {
+            match (resolve_from(list, fqdn, family, dns), &mut result) {
+                (Ok(answer), Ok(collected)) => {
+                    for address in answer.addresses {
+                        if !collected.addresses.iter().any(|a| a.get_ip() == address.get_ip()) {
+                            collected.addresses.push(address);
+                        }
+                    }
+                    collected.ttl = collected.ttl.min(answer.ttl);
+                },
+                (Ok(answer), result @ Err(_)) => *result = Ok(answer),
+                (Err(e), Err(last_error)) => *last_error = e,
+                (Err(e), Ok(_)) => debug!("Query for {fqdn} failed, keeping the answers collected so far: {e}")
+            }
+        }
+    }
+
+    if let Ok(collected) = &mut result {
+        collected.addresses.sort_by_key(|a| a.get_ip());
+    }
+    result
+}
+
+fn upstream_servers(dns: &DnsConfig) -> Vec<SocketAddr> {
+    if dns.get_servers().is_empty() {
+        system_nameservers()
+    }
+    else {
+        dns.get_servers().clone()
+    }
+}
+
+fn resolve_from(servers: &[SocketAddr], fqdn: &str, family: Family, dns: &DnsConfig) -> Result<Answer, String> {
+    let mut last_error = String::from("no nameserver available");
+
+    for attempt in 0..=dns.get_retries() {
+        for server in servers {
+            match resolve_with(*server, fqdn, family, dns) {
+                Ok(answer) => return answer,
+                Err(e) => {
+                    debug!("Query for {fqdn} to {server} failed on attempt {}: {e}", attempt + 1);
+                    last_error = format!("{server}: {e}");
+                }
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Queries a single server. The outer error means the server did not answer usably and the next
+/// one should be tried, the inner one is the server's final verdict.
+fn resolve_with(server: SocketAddr, fqdn: &str, family: Family, dns: &DnsConfig) -> Result<Result<Answer, String>, String> {
+    let mut addresses: Vec<Address> = Vec::new();
+    let mut ttl: Option<u32> = None;
+
+    for (qtype, qfamily) in [(TYPE_A, Family::V4), (TYPE_AAAA, Family::V6)] {
+        if !family.includes(qfamily) {
+            continue;
+        }
+        let (records, record_ttl) = query(server, fqdn, qtype, dns)?;
+        addresses.extend(records);
+        if let Some(t) = record_ttl {
+            ttl = Some(ttl.map_or(t, |current| current.min(t)));
+        }
+    }
+
+    if addresses.is_empty() {
+        return Ok(Err(format!("no {} records for {fqdn}", match family {
+            Family::V4 => "A",
+            Family::V6 => "AAAA",
+            Family::Any => "A or AAAA"
+        })));
+    }
+
+    addresses.sort_by_key(|a| a.get_ip());
+    addresses.dedup_by_key(|a| a.get_ip());
+    Ok(Ok(Answer {
+        addresses,
+        ttl: Duration::from_secs(ttl.unwrap_or(0) as u64)
+    }))
+}
+
+/// Parses an upstream server given as `ip` or `ip:port`, with IPv6 addresses in brackets when a
+/// port is given.
+pub fn parse_server(s: &str) -> Option<SocketAddr> {
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, 53));
+    }
+    s.parse::<SocketAddr>().ok()
+}
+
+fn system_nameservers() -> Vec<SocketAddr> {
+    let mut servers: Vec<SocketAddr> = Vec::new();
+    if let Ok(content) = fs::read_to_string(RESOLV_CONF) {
+        for line in content.lines() {
+            let mut parts = line.split_whitespace();
+            if parts.next() == Some("nameserver") {
+                if let Some(Ok(ip)) = parts.next().map(|s| s.parse::<IpAddr>()) {
+                    servers.push(SocketAddr::new(ip, 53));
+                }
+            }
+        }
+    }
+    if servers.is_empty() {
+        servers.push(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53));
+    }
+    servers
+}
+
+fn query(server: SocketAddr, fqdn: &str, qtype: u16, dns: &DnsConfig) -> Result<(Vec<Address>, Option<u32>), String> {
+    let id = query_id();
+    let request = build_query(id, fqdn, qtype)?;
+
+    let response = match dns.get_protocol() {
+        Protocol::Udp => {
+            let response = exchange_udp(server, id, &request, dns.get_timeout())?;
+            if response.len() > 2 && response[2] & 0x02 != 0 {
+                debug!("Answer for {fqdn} from {server} truncated, retrying over TCP");
+                exchange_tcp(server, id, &request, dns.get_timeout())?
+            }
+            else {
+                response
+            }
+        },
+        Protocol::Tcp => exchange_tcp(server, id, &request, dns.get_timeout())?
+    };
+
+    parse_response(&response, fqdn, qtype)
+}
+
+fn exchange_udp(server: SocketAddr, id: u16, request: &[u8], timeout: Duration) -> Result<Vec<u8>, String> {
+    let bind: SocketAddr = if server.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    }
+    else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind).map_err(|e| e.to_string())?;
+    socket.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    socket.connect(server).map_err(|e| e.to_string())?;
+    socket.send(request).map_err(|e| e.to_string())?;
+
+    let mut buf = [0u8; 4096];
+    loop {
+        let len = socket.recv(&mut buf).map_err(|e| e.to_string())?;
+        if len >= 2 && u16::from_be_bytes([buf[0], buf[1]]) == id {
+            return Ok(buf[..len].to_vec());
+        }
+    }
+}
+
+fn exchange_tcp(server: SocketAddr, id: u16, request: &[u8], timeout: Duration) -> Result<Vec<u8>, String> {
+    let mut stream = TcpStream::connect_timeout(&server, timeout).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+
+    let mut framed = (request.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(request);
+    stream.write_all(&framed).map_err(|e| e.to_string())?;
+
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).map_err(|e| e.to_string())?;
+    let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut buf).map_err(|e| e.to_string())?;
+
+    if buf.len() < 2 || u16::from_be_bytes([buf[0], buf[1]]) != id {
+        return Err("answer does not match query".to_string());
+    }
+    Ok(buf)
+}
+
+/// Unpredictable query ID, so answers are harder to spoof. Falls back to the randomly keyed
+/// standard hasher if `/dev/urandom` cannot be read.
+fn query_id() -> u16 {
+    let mut bytes = [0u8; 2];
+    if File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes)).is_ok() {
+        return u16::from_be_bytes(bytes);
+    }
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0));
+    hasher.finish() as u16
+}
+
+fn build_query(id: u16, fqdn: &str, qtype: u16) -> Result<Vec<u8>, String> {
+    let mut msg: Vec<u8> = Vec::with_capacity(512);
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&0x0100u16.to_be_bytes()); // recursion desired
+    msg.extend_from_slice(&1u16.to_be_bytes());
+    msg.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    for label in fqdn.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid name {fqdn}"));
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(msg)
+}
+
+/// Collects the addresses answering `qtype`, each with the CNAME chain leading from `fqdn` to it.
+/// Responses to any other question are rejected.
+fn parse_response(msg: &[u8], fqdn: &str, qtype: u16) -> Result<(Vec<Address>, Option<u32>), String> {
+    if msg.len() < 12 {
+        return Err("truncated response".to_string());
+    }
+    if msg[2] & 0x80 == 0 {
+        return Err("not a response".to_string());
+    }
+    let rcode = msg[3] & 0x0f;
+    if rcode != 0 && rcode != 3 {
+        return Err(format!("server answered with rcode {rcode}"));
+    }
+
+    if read_u16(msg, 4)? != 1 {
+        return Err("answer does not match query".to_string());
+    }
+    let (qname, mut pos) = read_name(msg, 12)?;
+    if qname != fqdn.trim_end_matches('.').to_ascii_lowercase() || read_u16(msg, pos)? != qtype || read_u16(msg, pos + 2)? != CLASS_IN {
+        return Err(format!("answer is for {qname} instead of {fqdn}"));
+    }
+    pos += 4;
+
+    if rcode == 3 {
+        return Ok((Vec::new(), None));
+    }
+    let ancount = read_u16(msg, 6)? as usize;
+
+    let mut records: Vec<(String, IpAddr)> = Vec::new();
+    let mut aliases: HashMap<String, String> = HashMap::new();
+    let mut ttl: Option<u32> = None;
+    for _ in 0..ancount {
+        let (owner, next) = read_name(msg, pos)?;
+        pos = next;
+        let rtype = read_u16(msg, pos)?;
+        let class = read_u16(msg, pos + 2)?;
+        let record_ttl = u32::from_be_bytes([
+            *msg.get(pos + 4).ok_or("truncated record")?,
+            *msg.get(pos + 5).ok_or("truncated record")?,
+            *msg.get(pos + 6).ok_or("truncated record")?,
+            *msg.get(pos + 7).ok_or("truncated record")?
+        ]);
+        let rdlength = read_u16(msg, pos + 8)? as usize;
+        let rdata = msg.get(pos + 10..pos + 10 + rdlength).ok_or("truncated record")?;
+        let rdata_pos = pos + 10;
+        pos += 10 + rdlength;
+
+        if class != CLASS_IN {
+            continue;
+        }
+        match (rtype, rdata.len()) {
+            (TYPE_A, 4) if qtype == TYPE_A => {
+                records.push((owner, IpAddr::V4(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]))));
+            },
+            (TYPE_AAAA, 16) if qtype == TYPE_AAAA => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rdata);
+                records.push((owner, IpAddr::V6(Ipv6Addr::from(octets))));
+            },
+            (TYPE_CNAME, _) => {
+                let (alias, _) = read_name(msg, rdata_pos)?;
+                aliases.insert(owner, alias);
+            },
+            _ => continue
+        }
+        ttl = Some(ttl.map_or(record_ttl, |t| t.min(record_ttl)));
+    }
+
+    // Records of names the queried one does not lead to are no answer to the query.
+    let addresses = records.into_iter()
+        .filter_map(|(owner, ip)| match cname_chain(fqdn, &owner, &aliases) {
+            Some(chain) => Some(Address::new(ip, chain)),
+            None => {
+                debug!("Ignoring {ip} of {owner} in the answer for {fqdn}");
+                None
+            }
+        })
+        .collect();
+    Ok((addresses, ttl))
+}
+
+/// Follows the CNAME records from `fqdn` to `owner`, returning the names passed on the way,
+/// `owner` included. Empty if `fqdn` itself holds the address, `None` if `owner` cannot be
+/// reached from it.
+fn cname_chain(fqdn: &str, owner: &str, aliases: &HashMap<String, String>) -> Option<Vec<String>> {
+    let mut chain: Vec<String> = Vec::new();
+    let mut name = fqdn.trim_end_matches('.').to_ascii_lowercase();
+    while name != owner {
+        if chain.len() >= MAX_CNAME_CHAIN {
+            return None;
+        }
+        let alias = aliases.get(&name)?;
+        chain.push(alias.clone());
+        name = alias.clone();
+    }
+    Some(chain)
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Result<u16, String> {
+    match msg.get(pos..pos + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+        None => Err("truncated response".to_string())
+    }
+}
+
+/// Reads a possibly compressed name, returning it lowercased without the trailing dot, along with
+/// the position right after it.
+fn read_name(msg: &[u8], mut pos: usize) -> Result<(String, usize), String> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end: Option<usize> = None;
+    for _ in 0..MAX_POINTERS {
+        let len = *msg.get(pos).ok_or("truncated name")? as usize;
+        if len == 0 {
+            return Ok((labels.join("."), end.unwrap_or(pos + 1)));
+        }
+        if len & 0xc0 == 0xc0 {
+            let low = *msg.get(pos + 1).ok_or("truncated name")? as usize;
+            end.get_or_insert(pos + 2);
+            pos = (len & 0x3f) << 8 | low;
+            continue;
+        }
+        let label = msg.get(pos + 1..pos + 1 + len).ok_or("truncated name")?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        pos += len + 1;
+    }
+    Err("name compression loop".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    /// Record served by the stub: owner name, type and data.
+    type Record = (&'static str, u16, Vec<u8>);
+
+    fn encode_name(name: &str) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        for label in name.split('.') {
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label.as_bytes());
+        }
+        buf.push(0);
+        buf
+    }
+
+    /// Builds the response to `request`, answering the question with `records`, or for `question`
+    /// instead if given. The question's own name is compressed to a pointer in the records.
+    fn respond(request: &[u8], records: &[Record], truncated: bool, question: Option<&str>) -> Vec<u8> {
+        let (qname, end) = read_name(request, 12).unwrap();
+        let mut msg = request[..2].to_vec();
+        msg.extend_from_slice(&(0x8180u16 | if truncated {0x0200} else {0}).to_be_bytes());
+        msg.extend_from_slice(&1u16.to_be_bytes());
+        msg.extend_from_slice(&(records.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&[0, 0, 0, 0]);
+        match question {
+            Some(name) => {
+                msg.extend_from_slice(&encode_name(name));
+                msg.extend_from_slice(&request[end..end + 4]);
+            },
+            None => msg.extend_from_slice(&request[12..end + 4])
+        }
+        for (owner, rtype, rdata) in records {
+            if *owner == qname {
+                msg.extend_from_slice(&[0xc0, 12]);
+            }
+            else {
+                msg.extend_from_slice(&encode_name(owner));
+            }
+            msg.extend_from_slice(&rtype.to_be_bytes());
+            msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+            msg.extend_from_slice(&300u32.to_be_bytes());
+            msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            msg.extend_from_slice(rdata);
+        }
+        msg
+    }
+
+    /// Canned answers of the stub, by question name and type.
+    fn records(qname: &str, qtype: u16) -> Vec<Record> {
+        match (qname, qtype) {
+            ("a.test", TYPE_A) => vec![("a.test", TYPE_A, vec![10, 0, 0, 1]), ("a.test", TYPE_A, vec![10, 0, 0, 2])],
+            ("a.test", TYPE_AAAA) => vec![("a.test", TYPE_AAAA, "fd00::1".parse::<Ipv6Addr>().unwrap().octets().to_vec())],
+            ("alias.test", TYPE_A) => vec![
+                ("alias.test", TYPE_CNAME, encode_name("edge.cdn.test")),
+                ("edge.cdn.test", TYPE_CNAME, encode_name("node.cdn.test")),
+                ("node.cdn.test", TYPE_A, vec![10, 1, 0, 1])
+            ],
+            ("big.test", TYPE_A) => (1..=3).map(|i| ("big.test", TYPE_A, vec![10, 2, 0, i])).collect(),
+            ("mixed.test", TYPE_A) => vec![
+                ("mixed.test", TYPE_CNAME, encode_name("edge.cdn.test")),
+                ("evil.test", TYPE_A, vec![6, 6, 6, 6]),
+                ("edge.cdn.test", TYPE_A, vec![10, 3, 0, 1]),
+                ("other.cdn.test", TYPE_CNAME, encode_name("mixed.test")),
+                ("other.cdn.test", TYPE_A, vec![6, 6, 6, 7])
+            ],
+            _ => Vec::new()
+        }
+    }
+
+    /// Serves the canned answers over UDP and TCP on the same local port. Over UDP, `big.test` is
+    /// answered truncated and `spoof.test` with the question changed to `a.test`, after an answer
+    /// with a wrong ID.
+    fn stub() -> SocketAddr {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((len, peer)) = udp.recv_from(&mut buf) {
+                let request = &buf[..len];
+                let (qname, end) = read_name(request, 12).unwrap();
+                let qtype = read_u16(request, end).unwrap();
+                let response = match qname.as_str() {
+                    "big.test" => respond(request, &[], true, None),
+                    "spoof.test" => {
+                        let mut wrong_id = respond(request, &records("a.test", qtype), false, None);
+                        wrong_id[0] ^= 0xff;
+                        udp.send_to(&wrong_id, peer).unwrap();
+                        respond(request, &records("a.test", qtype), false, Some("a.test"))
+                    },
+                    _ => respond(request, &records(&qname, qtype), false, None)
+                };
+                udp.send_to(&response, peer).unwrap();
+            }
+        });
+        thread::spawn(move || {
+            for mut stream in tcp.incoming().map_while(Result::ok) {
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len).unwrap();
+                let mut request = vec![0u8; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut request).unwrap();
+                let (qname, end) = read_name(&request, 12).unwrap();
+                let response = respond(&request, &records(&qname, read_u16(&request, end).unwrap()), false, None);
+                let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+                framed.extend_from_slice(&response);
+                stream.write_all(&framed).unwrap();
+            }
+        });
+        addr
+    }
+
+    fn ips(addresses: &[Address]) -> Vec<String> {
+        addresses.iter().map(|a| a.get_ip().to_string()).collect()
+    }
+
+    #[test]
+    fn resolves_both_families() {
+        let server = stub();
+        let answer = resolve_with(server, "a.test", Family::Any, &DnsConfig::default()).unwrap().unwrap();
+        assert_eq!(ips(answer.get_addresses()), ["10.0.0.1", "10.0.0.2", "fd00::1"]);
+        assert_eq!(answer.get_ttl(), Duration::from_secs(300));
+
+        let answer = resolve_with(server, "a.test", Family::V6, &DnsConfig::default()).unwrap().unwrap();
+        assert_eq!(ips(answer.get_addresses()), ["fd00::1"]);
+    }
+
+    #[test]
+    fn follows_cname_chain() {
+        let (addresses, _) = query(stub(), "Alias.Test.", TYPE_A, &DnsConfig::default()).unwrap();
+        assert_eq!(ips(&addresses), ["10.1.0.1"]);
+        assert_eq!(addresses[0].get_chain(), &["edge.cdn.test", "node.cdn.test"]);
+    }
+
+    #[test]
+    fn ignores_records_not_reached_from_queried_name() {
+        let (addresses, _) = query(stub(), "mixed.test", TYPE_A, &DnsConfig::default()).unwrap();
+        assert_eq!(ips(&addresses), ["10.3.0.1"]);
+        assert_eq!(addresses[0].get_chain(), &["edge.cdn.test"]);
+    }
+
+    #[test]
+    fn retries_truncated_answer_over_tcp() {
+        let (addresses, _) = query(stub(), "big.test", TYPE_A, &DnsConfig::default()).unwrap();
+        assert_eq!(ips(&addresses), ["10.2.0.1", "10.2.0.2", "10.2.0.3"]);
+    }
+
+    #[test]
+    fn reports_missing_records() {
+        let answer = resolve_with(stub(), "missing.test", Family::Any, &DnsConfig::default()).unwrap();
+        assert_eq!(answer.err().unwrap(), "no A or AAAA records for missing.test");
+    }
+
+    #[test]
+    fn rejects_answer_to_other_question() {
+        let error = query(stub(), "spoof.test", TYPE_A, &DnsConfig::default()).unwrap_err();
+        assert_eq!(error, "answer is for a.test instead of spoof.test");
+    }
+}
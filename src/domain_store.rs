@@ -1,58 +1,116 @@
 use std::collections::HashMap;
 
-use crate::domain::Domain;
+use crate::{config::{DnsConfig, IpsetConfig}, domain::Domain, logging::{debug, info}, network::IpSet};
 
 pub struct DomainStore {
-    domains: HashMap<String, Domain>
+    domains: HashMap<String, Domain>,
+    dns: DnsConfig,
+    ipsets: IpsetConfig
 }
 
 impl DomainStore {
-    pub fn new() -> Self {
+    pub fn new(dns: DnsConfig, ipsets: IpsetConfig) -> Self {
         Self {
-            domains: HashMap::new()
+            domains: HashMap::new(),
+            dns,
+            ipsets
         }
     }
 
     pub fn ingest_domain(&mut self, domain: Domain) {
         if let Some(stored) = self.domains.get_mut(&domain.get_fqdn()) {
-            if stored.get_interval() > domain.get_interval() {
-                stored.set_interval(domain.get_interval());
-            }
+            stored.merge(&domain);
         }
         else {
             self.domains.insert(domain.get_fqdn(), domain);
         }
     }
 
+    /// Replaces the stored domains with those of `fresh`, keeping the resolution state of domains
+    /// present in both and dropping those no longer referenced. Returns the number of domains added
+    /// or removed.
+    pub fn reconcile(&mut self, fresh: DomainStore) -> usize {
+        let mut changed: usize = 0;
+        let mut domains: HashMap<String, Domain> = HashMap::new();
+        for (fqdn, domain) in fresh.domains {
+            match self.domains.remove(&fqdn) {
+                Some(mut stored) => {
+                    stored.adopt(&domain);
+                    domains.insert(fqdn, stored);
+                },
+                None => {
+                    debug!("Adding domain {fqdn}");
+                    changed += 1;
+                    domains.insert(fqdn, domain);
+                }
+            }
+        }
+        for fqdn in self.domains.keys() {
+            debug!("Dropping domain {fqdn}");
+            changed += 1;
+        }
+        self.domains = domains;
+        self.dns = fresh.dns;
+        self.ipsets = fresh.ipsets;
+        changed
+    }
+
     pub fn update(&mut self) -> usize {
         let mut changed: usize = 0;
         for domain in self.domains.values_mut() {
-            if let Some(change_applied) = domain.try_update() {
+            if let Some(change_applied) = domain.try_update(&self.dns) {
                 if change_applied {
                     changed += 1;
                 }
             }
         }
         if changed > 0 {
-            println!("Updated {} domains", changed);
+            info!("Updated {} domains", changed);
         }
         changed
     }
 
-    pub fn render(&self) -> String {
-        let mut buf: String = String::new();
-        for domain in self.domains.values() {
-            if let Some(ipset) = domain.try_render() {
-                buf += ipset.as_str();
+    /// Re-resolves all domains regardless of their interval.
+    pub fn refresh(&mut self) -> usize {
+        let mut changed: usize = 0;
+        for domain in self.domains.values_mut() {
+            if let Some(true) = domain.update(&self.dns) {
+                changed += 1;
             }
         }
-        buf
+        info!("Refreshed {} domains, {} changed", self.domains.len(), changed);
+        changed
+    }
+
+    /// IPSets of the given domains, sorted by name.
+    pub fn get_ipsets(&self, fqdns: &[String]) -> Vec<IpSet> {
+        let mut fqdns: Vec<&String> = fqdns.iter().collect();
+        fqdns.sort();
+        fqdns.dedup();
+
+        fqdns.into_iter()
+            .filter_map(|fqdn| self.domains.get(fqdn))
+            .flat_map(|domain| domain.get_ipsets(&self.dns, &self.ipsets))
+            .collect()
     }
 
-    pub fn get(&self, fqdn: &String) -> Option<&Domain> {
+    pub fn get_fqdns(&self) -> Vec<String> {
+        self.domains.keys().cloned().collect()
+    }
+
+    pub fn get_ipset_config(&self) -> &IpsetConfig {
+        &self.ipsets
+    }
+
+    pub fn get(&self, fqdn: &str) -> Option<&Domain> {
         self.domains.get(fqdn)
     }
 
+    #[cfg(test)]
+    pub fn get_mut(&mut self, fqdn: &str) -> Option<&mut Domain> {
+        self.domains.get_mut(fqdn)
+    }
+
     pub fn len(&self) -> usize {
         self.domains.len()
     }
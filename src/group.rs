@@ -1,114 +1,437 @@
-use std::{fs::File, io::{BufRead, BufReader}};
+use std::{collections::{HashMap, HashSet}, fs::File, io::{BufRead, BufReader}, net::IpAddr};
 
-use crate::{domain::Domain, domain_store::DomainStore, rule::DynRule};
+use crate::{diagnostic::{tokenize, Diagnostic, ParseError}, domain::{entry, parse_bool, Domain, Family}, domain_store::DomainStore, logging::debug, network::{Entry, IpSet, Network}, rule::{DynRule, Endpoint}, target::TargetKind};
 
 pub struct Group {
     name: String,
+    file: String,
     domains: Vec<String>,
+    /// Address families the group declared each of its domains with, by FQDN.
+    families: HashMap<String, Family>,
+    networks: Vec<Network>,
+    aggregate: bool,
+    attachments: Vec<Attachment>,
     static_rules: Vec<String>,
     dynamic_rules: Vec<DynRule>
 }
 
 impl Group {
-    pub fn read(name: String, path: String, store: &mut DomainStore) -> Option<Self> {
-        if let Ok(file) = File::open(path) {
-            let source = BufReader::new(file);
-            let mut state = ReadState::None;
-            let mut domains: Vec<String> = Vec::new();
-            let mut static_rules: Vec<String> = Vec::new();
-            let mut dynamic_rules: Vec<DynRule> = Vec::new();
-
-            for line in source.lines() {
-                match line {
-                    Ok(line) => {
-                        if line.len() == 0 {
-                            continue;
-                        }
-
-                        if let Some(s) = ReadState::from_string(&line) {
-                            state = s;
-                            continue;
-                        }
-
-                        match state {
-                            ReadState::None => {
-                                return None;
-                            },
-                            ReadState::Domains => {
-                                if let Some(domain) = Domain::from_string(&line) {
-                                    if !domains.contains(&domain.get_fqdn()) {
-                                        domains.push(domain.get_fqdn());
-                                    }
-                                    store.ingest_domain(domain);
-                                }
-                                else {
-                                    println!("Failed to load domain from: {line}");
+    pub fn read(name: String, path: String, store: &mut DomainStore, diagnostics: &mut Vec<Diagnostic>) -> Option<Self> {
+        let file = File::open(&path).ok()?;
+        Self::parse(name, path, BufReader::new(file), store, diagnostics)
+    }
+
+    /// Parses the content of a `.group` file read from `path`, ingesting its domains into `store`.
+    fn parse(name: String, path: String, source: impl BufRead, store: &mut DomainStore, diagnostics: &mut Vec<Diagnostic>) -> Option<Self> {
+        let mut state = ReadState::None;
+        let mut domains: Vec<String> = Vec::new();
+        let mut families: HashMap<String, Family> = HashMap::new();
+        let mut networks: Vec<Network> = Vec::new();
+        let mut attachments: Vec<Attachment> = Vec::new();
+        let mut static_rules: Vec<String> = Vec::new();
+        let mut dynamic_rules: Vec<DynRule> = Vec::new();
+        let mut parsed: Vec<Domain> = Vec::new();
+        let mut options = GroupOptions::default();
+
+        for (idx, line) in source.lines().enumerate() {
+            let line_no = idx + 1;
+            match line {
+                Ok(line) => {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+
+                    if let Some(s) = ReadState::from_string(&line) {
+                        state = s;
+                        continue;
+                    }
+                    else if line.starts_with('[') && line.trim_end().ends_with(']') {
+                        diagnostics.push(ParseError::new(1, format!("unknown section {}", line.trim_end())).locate(&path, line_no));
+                        state = ReadState::Unknown;
+                        continue;
+                    }
+
+                    match state {
+                        ReadState::None => {
+                            diagnostics.push(ParseError::new(1, "line outside of a section".to_string()).locate(&path, line_no));
+                        },
+                        ReadState::Unknown => {},
+                        ReadState::Options => {
+                            let (key, value) = match line.split_once('=') {
+                                Some((k, v)) => (k.trim(), v.trim()),
+                                None => {
+                                    diagnostics.push(ParseError::new(1, "expected key = value".to_string()).locate(&path, line_no));
+                                    continue;
                                 }
-                            },
-                            ReadState::StaticRules => {
-                                if !static_rules.contains(&line) {
-                                    static_rules.push(line);
+                            };
+                            if let Err(e) = options.set(key, value) {
+                                let column = line.find(value).unwrap_or(0) + 1;
+                                diagnostics.push(ParseError::new(column, e).locate(&path, line_no));
+                            }
+                        },
+                        ReadState::Domains => {
+                            match Domain::parse(&line) {
+                                Ok(domain) => parsed.push(domain),
+                                Err(e) => diagnostics.push(e.locate(&path, line_no))
+                            }
+                        },
+                        ReadState::Networks => {
+                            match Network::parse(line.trim()) {
+                                Ok(network) => {
+                                    if !networks.contains(&network) {
+                                        networks.push(network);
+                                    }
+                                },
+                                Err(e) => {
+                                    let column = line.len() - line.trim_start().len() + 1;
+                                    diagnostics.push(ParseError::new(column, e).locate(&path, line_no));
                                 }
-                            },
-                            ReadState::DynamicRules => {
-                                if let Some(rule) = DynRule::from_string(line) {
+                            }
+                        },
+                        ReadState::Attach => {
+                            match Attachment::parse(&line) {
+                                Ok(attachment) => {
+                                    if !attachments.iter().any(|a| a.target == attachment.target) {
+                                        attachments.push(attachment);
+                                    }
+                                },
+                                Err(e) => diagnostics.push(e.locate(&path, line_no))
+                            }
+                        },
+                        ReadState::StaticRules => {
+                            if !static_rules.contains(&line) {
+                                static_rules.push(line);
+                            }
+                        },
+                        ReadState::DynamicRules => {
+                            match DynRule::parse(&line) {
+                                Ok(rule) => {
+                                    debug!("Parsed dynamic rule {rule}");
                                     if !dynamic_rules.contains(&rule) {
                                         dynamic_rules.push(rule);
                                     }
-                                }
-                            },
-                        }
-                    },
-                    Err(_) => {
-                        return None;
+                                },
+                                Err(e) => diagnostics.push(e.locate(&path, line_no))
+                            }
+                        },
                     }
+                },
+                Err(e) => {
+                    diagnostics.push(ParseError::new(1, format!("failed to read file: {e}")).locate(&path, line_no));
+                    return None;
                 }
             }
+        }
 
-            return Some(Self {
-                name,
-                domains,
-                static_rules,
-                dynamic_rules
-            })
+        for mut domain in parsed {
+            domain.inherit(options.family, options.split);
+            if !domains.contains(&domain.get_fqdn()) {
+                domains.push(domain.get_fqdn());
+            }
+            let family = match families.get(&domain.get_fqdn()) {
+                Some(family) => family.union(domain.get_family()),
+                None => domain.get_family()
+            };
+            families.insert(domain.get_fqdn(), family);
+            store.ingest_domain(domain);
         }
-        None
+
+        Some(Self {
+            name,
+            file: path.rsplit('/').next().unwrap_or(&path).to_string(),
+            domains,
+            families,
+            networks,
+            aggregate: options.aggregate.unwrap_or(false),
+            attachments,
+            static_rules,
+            dynamic_rules
+        })
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
     }
 
-    pub fn render(&self, store: &DomainStore) -> String {
+    pub fn get_domains(&self) -> &Vec<String> {
+        &self.domains
+    }
+
+    /// Name of the IPSet holding the group's own networks, and in aggregate mode the addresses of
+    /// all its domains as well.
+    pub fn get_ipset_name(&self) -> String {
+        format!("group_{}", self.name)
+    }
+
+    /// The group-owned IPSet, if it has any entries.
+    pub fn get_ipset(&self, store: &DomainStore) -> Option<IpSet> {
+        let mut entries: Vec<Entry> = self.networks.iter()
+            .map(|network| Entry::new(*network, None, None))
+            .collect();
+        if self.aggregate {
+            let mut seen: HashSet<IpAddr> = HashSet::new();
+            for domain in self.verified_domains(store) {
+                let family = self.get_family(domain);
+                for address in domain.get_addresses().iter().filter(|a| family.contains(&a.get_ip())) {
+                    if seen.insert(address.get_ip()) {
+                        entries.push(entry(&domain.get_fqdn(), address));
+                    }
+                }
+            }
+        }
+        if entries.is_empty() {
+            return None;
+        }
+
+        let comment = if self.aggregate {"addresses"} else {"networks"};
+        Some(IpSet::new(self.get_ipset_name(), format!("{comment} of group {}", self.name), entries, store.get_ipset_config()))
+    }
+
+    /// Address family the group declared the domain with, which may be narrower than the one it
+    /// is resolved for if other declarations ask for more.
+    fn get_family(&self, domain: &Domain) -> Family {
+        self.families.get(&domain.get_fqdn()).copied().unwrap_or(Family::Any)
+    }
+
+    fn verified_domains<'a>(&'a self, store: &'a DomainStore) -> impl Iterator<Item = &'a Domain> {
+        self.domains.iter()
+            .filter_map(|fqdn| store.get(fqdn))
+            .filter(|domain| domain.verify())
+    }
+
+    /// Names of the targets the group is attached to.
+    pub fn get_attached_targets(&self) -> Vec<String> {
+        self.attachments.iter().map(|a| a.target.clone()).collect()
+    }
+
+    /// Renders the `GROUP` rule referencing this group if it is attached to the given target.
+    pub fn render_reference(&self, target: &str) -> Option<String> {
+        let attachment = self.attachments.iter().find(|a| a.target == target)?;
+        let mut buf = format!("GROUP {}", self.name);
+        if let Some(iface) = &attachment.iface {
+            buf += format!(" -i {iface}").as_str();
+        }
+        buf += format!(" # attached by {}\n", self.file).as_str();
+        Some(buf)
+    }
+
+    /// IPSets the dynamic rules are rendered against, each with a description of its origin.
+    fn ipsets(&self, store: &DomainStore) -> Vec<(String, String)> {
+        let mut ipsets: Vec<(String, String)> = Vec::new();
+        if self.aggregate {
+            if !self.networks.is_empty() || self.verified_domains(store).next().is_some() {
+                ipsets.push((self.get_ipset_name(), format!("all of {}", self.file)));
+            }
+            return ipsets;
+        }
+        for domain in self.verified_domains(store) {
+            ipsets.push((domain.get_ipset_name(self.get_family(domain)), format!("{} from {}", domain.get_fqdn(), self.file)));
+        }
+        if !self.networks.is_empty() {
+            ipsets.push((self.get_ipset_name(), format!("networks from {}", self.file)));
+        }
+        ipsets
+    }
+
+    /// Names of the groups whose sets the dynamic rules use as their opposite endpoint.
+    pub fn get_referenced_groups(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for rule in &self.dynamic_rules {
+            if let Some(Endpoint::Group(name)) = rule.get_opposite() {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    pub fn render(&self, store: &DomainStore, kind: &TargetKind, groups: &[Group]) -> String {
         let mut buf: String = format!("[group {}]\n\n", self.name);
+        buf += self.render_rules(store, kind, groups).as_str();
+        buf += "\n";
+        buf
+    }
+
+    /// Renders the static rules followed by every dynamic rule for each of the group's IPSets and,
+    /// if its opposite endpoint is another group, each of that group's IPSets. Rules of directions
+    /// the target does not accept are left out.
+    pub fn render_rules(&self, store: &DomainStore, kind: &TargetKind, groups: &[Group]) -> String {
+        let scope = kind.get_scope();
+        let mut buf: String = String::new();
         for rule in &self.static_rules {
             buf += format!("{rule}\n").as_str();
         }
-        for rule in &self.dynamic_rules {
-            for fqdn in &self.domains {
-                if let Some(domain) = store.get(&fqdn) {
-                    if domain.verify() {
-                        buf += rule.render(domain).as_str();
-                    }
+        let ipsets = self.ipsets(store);
+        for rule in self.dynamic_rules.iter().filter(|r| kind.allows(r.get_direction())) {
+            let opposites: Vec<Option<String>> = match rule.get_opposite() {
+                None => vec![None],
+                Some(Endpoint::Static(endpoint)) => vec![Some(endpoint.clone())],
+                Some(Endpoint::Group(name)) => groups.iter()
+                    .filter(|g| &g.name == name)
+                    .flat_map(|g| g.ipsets(store))
+                    .map(|(ipset, _)| Some(format!("+{scope}/{ipset}")))
+                    .collect()
+            };
+            for (ipset, origin) in &ipsets {
+                for opposite in &opposites {
+                    buf += rule.render(ipset, opposite.as_deref(), scope, origin).as_str();
                 }
             }
         }
-        buf += "\n";
         buf
     }
 }
 
+/// Settings of the `[options]` section, applying to the whole group.
+#[derive(Default)]
+struct GroupOptions {
+    family: Option<Family>,
+    split: Option<bool>,
+    aggregate: Option<bool>
+}
+
+impl GroupOptions {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "family" => {
+                self.family = Some(Family::from_string(value).ok_or(format!("unknown address family {value}"))?);
+            },
+            "split" => {
+                self.split = Some(parse_bool(value).ok_or(format!("expected yes or no for split, found {value}"))?);
+            },
+            "aggregate" => {
+                self.aggregate = Some(parse_bool(value).ok_or(format!("expected yes or no for aggregate, found {value}"))?);
+            },
+            _ => return Err(format!("unknown option {key}"))
+        }
+        Ok(())
+    }
+}
+
+/// Line of the `[attach]` section: the name of a configured target, optionally followed by
+/// `-i <iface>` to only apply the group to one interface.
+struct Attachment {
+    target: String,
+    iface: Option<String>
+}
+
+impl Attachment {
+    fn parse(s: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(s);
+        match tokens.as_slice() {
+            [(_, target)] => Ok(Self {
+                target: target.to_string(),
+                iface: None
+            }),
+            [(_, target), (_, "-i"), (column, iface)] => {
+                if !iface.chars().all(|c| c.is_ascii_alphanumeric() || ".-_".contains(c)) {
+                    return Err(ParseError::new(*column, format!("invalid interface {iface}")));
+                }
+                Ok(Self {
+                    target: target.to_string(),
+                    iface: Some(iface.to_string())
+                })
+            },
+            [_, (column, _), ..] => Err(ParseError::new(*column, "expected target name, optionally followed by -i <iface>".to_string())),
+            [] => Err(ParseError::new(1, "expected target name".to_string()))
+        }
+    }
+}
+
 #[derive(PartialEq)]
 enum ReadState {
     None,
+    Unknown,
+    Options,
     Domains,
+    Networks,
+    Attach,
     StaticRules,
     DynamicRules
 }
 
 impl ReadState {
-    pub fn from_string(s: &String) -> Option<Self> {
-        match s.to_ascii_lowercase().as_str() {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s.trim_end().to_ascii_lowercase().as_str() {
+            "[options]" => Some(Self::Options),
             "[domains]" => Some(Self::Domains),
+            "[networks]" => Some(Self::Networks),
+            "[attach]" => Some(Self::Attach),
             "[static rules]" => Some(Self::StaticRules),
             "[dynamic rules]" => Some(Self::DynamicRules),
             _ => None
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DnsConfig, IpsetConfig};
+
+    fn group(name: &str, content: &str, store: &mut DomainStore) -> Group {
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        let group = Group::parse(name.to_string(), format!("{name}.group"), content.as_bytes(), store, &mut diagnostics).unwrap();
+        assert!(diagnostics.is_empty(), "{}", diagnostics[0]);
+        group
+    }
+
+    fn resolve(store: &mut DomainStore, fqdn: &str, ips: &[&str]) {
+        store.get_mut(fqdn).unwrap().set_resolved(ips);
+    }
+
+    #[test]
+    fn references_family_sets_of_narrower_declarations() {
+        let mut store = DomainStore::new(DnsConfig::default(), IpsetConfig::default());
+        let v4 = group("v4", "[options]\nfamily = v4\n[domains]\na.test\n[dynamic rules]\nOUT ACCEPT", &mut store);
+        let any = group("any", "[domains]\na.test\n[dynamic rules]\nOUT ACCEPT", &mut store);
+        resolve(&mut store, "a.test", &["10.0.0.1", "fd00::1"]);
+
+        let names: Vec<String> = store.get_ipsets(&["a.test".to_string()]).iter().map(|i| i.get_name().to_string()).collect();
+        assert_eq!(names, ["domain_a_test", "domain_a_test_v4", "domain_a_test_v6"]);
+        assert_eq!(v4.render_rules(&store, &TargetKind::Cluster, &[]),
+            "OUT ACCEPT -dest +dc/domain_a_test_v4 -log nolog # a.test from v4.group\n");
+        assert_eq!(any.render_rules(&store, &TargetKind::Cluster, &[]),
+            "OUT ACCEPT -dest +dc/domain_a_test -log nolog # a.test from any.group\n");
+    }
+
+    #[test]
+    fn renders_networks_set() {
+        let mut store = DomainStore::new(DnsConfig::default(), IpsetConfig::default());
+        let net = group("net", "[networks]\n10.0.0.0/24\n  fd00::/64\n10.0.0.0/24\n[dynamic rules]\nIN SSH(ACCEPT)", &mut store);
+
+        assert_eq!(net.get_ipset(&store).unwrap().render(), "\
+[IPSET group_net] # networks of group net
+
+10.0.0.0/24
+fd00::/64
+
+");
+        assert_eq!(net.render(&store, &TargetKind::Cluster, &[]), "\
+[group net]
+
+IN SSH(ACCEPT) -source +dc/group_net -log nolog # networks from net.group
+
+");
+    }
+
+    #[test]
+    fn aggregates_domains_and_networks_into_one_set() {
+        let mut store = DomainStore::new(DnsConfig::default(), IpsetConfig::default());
+        let all = group("all", "[options]\naggregate = yes\n[domains]\na.test family=v4\nb.test\n[networks]\n192.168.0.0/16\n[dynamic rules]\nOUT ACCEPT", &mut store);
+        resolve(&mut store, "a.test", &["10.0.0.1", "fd00::1"]);
+        resolve(&mut store, "b.test", &["10.0.0.1", "fd00::2"]);
+
+        assert_eq!(all.get_ipset(&store).unwrap().render(), "\
+[IPSET group_all] # addresses of group all
+
+192.168.0.0/16
+10.0.0.1/32 # a.test, last seen 1970-01-01T00:00:00Z
+fd00::2/128 # b.test, last seen 1970-01-01T00:00:00Z
+
+");
+        assert_eq!(all.render_rules(&store, &TargetKind::Cluster, &[]),
+            "OUT ACCEPT -dest +dc/group_all -log nolog # all of all.group\n");
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug)]
+pub enum Level {
+    Error = 0,
+    Warning = 1,
+    Info = 2,
+    Debug = 3
+}
+
+impl Level {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" | "err" => Some(Self::Error),
+            "warning" | "warn" => Some(Self::Warning),
+            "info" => Some(Self::Info),
+            "debug" => Some(Self::Debug),
+            _ => None
+        }
+    }
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Sends all messages to stderr, keeping stdout free for command output.
+pub fn set_stderr(stderr: bool) {
+    STDERR.store(stderr, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn log(level: Level, msg: String) {
+    if enabled(level) {
+        if level <= Level::Warning || STDERR.load(Ordering::Relaxed) {
+            eprintln!("{msg}");
+        }
+        else {
+            println!("{msg}");
+        }
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Error, format!($($arg)*)) }
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Warning, format!($($arg)*)) }
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Info, format!($($arg)*)) }
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Debug, format!($($arg)*)) }
+}
+
+pub(crate) use {error, warning, info, debug};
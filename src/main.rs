@@ -1,6 +1,9 @@
+use std::process;
+
+use cli::{Cli, Command};
 use config::Config;
 use processor::ProcessorSignal;
-use signal_hook::{consts::{SIGINT, SIGTERM}, iterator::Signals};
+use signal_hook::{consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1}, iterator::Signals};
 
 mod group;
 mod domain;
@@ -10,11 +13,48 @@ mod orig_cache;
 mod config;
 mod processor;
 mod module;
+mod logging;
+mod target;
+mod atomic_file;
+mod ruleset;
+mod diff;
+mod cli;
+mod diagnostic;
+mod network;
+mod nft;
 
 fn main() {
-    let mut signals = Signals::new(&[SIGINT, SIGTERM]).unwrap();
+    let cli = Cli::from_args();
+
+    let config = match Config::find(cli.get_config()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(2);
+        }
+    };
+    logging::set_level(config.get_log_level());
+
+    let dry_run = cli.is_dry_run();
+    match cli.get_command() {
+        Command::Run if !dry_run => run(config),
+        Command::Run | Command::Apply => {
+            logging::set_stderr(true);
+            process::exit(processor::apply(&config, cli.get_target(), dry_run));
+        },
+        Command::Render => {
+            logging::set_stderr(true);
+            process::exit(processor::render(&config, cli.get_target()));
+        },
+        Command::Check => {
+            logging::set_stderr(true);
+            process::exit(processor::check(&config));
+        }
+    }
+}
 
-    let config = Config::from_args();
+fn run(config: Config) {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP, SIGUSR1]).unwrap();
 
     let processor = processor::start(config);
 
@@ -25,6 +65,8 @@ fn main() {
                 processor.join();
                 break;
             },
+            SIGHUP => processor.send(ProcessorSignal::Reload),
+            SIGUSR1 => processor.send(ProcessorSignal::Refresh),
             _ => {}
         }
     }
@@ -0,0 +1,265 @@
+use std::{fmt, net::IpAddr};
+
+use crate::config::IpsetConfig;
+
+/// IPv4 or IPv6 network in CIDR notation, a single address having the full prefix length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8
+}
+
+impl Network {
+    /// Parses `ip` or `ip/prefix`, rejecting networks with host bits set.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None)
+        };
+        let addr = addr.parse::<IpAddr>().map_err(|_| format!("invalid address {addr}"))?;
+        let max = max_prefix(&addr);
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().ok().filter(|p| *p <= max).ok_or(format!("invalid prefix length {p}"))?,
+            None => max
+        };
+
+        let network = Self::new(addr, prefix);
+        if network.addr != addr {
+            return Err(format!("{s} has host bits set, the network is {network}"));
+        }
+        Ok(network)
+    }
+
+    /// Network of the given prefix length containing `addr`.
+    pub fn new(addr: IpAddr, prefix: u8) -> Self {
+        let prefix = prefix.min(max_prefix(&addr));
+        let addr = match addr {
+            IpAddr::V4(a) => IpAddr::V4((u32::from(a) & (u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0))).into()),
+            IpAddr::V6(a) => IpAddr::V6((u128::from(a) & (u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0))).into())
+        };
+        Self {
+            addr,
+            prefix
+        }
+    }
+
+    /// Network containing only `addr`.
+    pub fn host(addr: IpAddr) -> Self {
+        Self::new(addr, max_prefix(&addr))
+    }
+
+    pub fn is_ipv4(&self) -> bool {
+        self.addr.is_ipv4()
+    }
+
+    pub fn contains(&self, other: &Network) -> bool {
+        self.addr.is_ipv4() == other.addr.is_ipv4()
+        &&
+        self.prefix <= other.prefix
+        &&
+        Self::new(other.addr, self.prefix).addr == self.addr
+    }
+
+    /// Network covering exactly both, if they are the two halves of it and it is not wider than
+    /// `max_widening` bits beyond a single address.
+    fn merge(&self, other: &Network, max_widening: u8) -> Option<Network> {
+        if self == other
+            || self.addr.is_ipv4() != other.addr.is_ipv4()
+            || self.prefix != other.prefix
+            || self.prefix == 0
+            || max_prefix(&self.addr) - self.prefix >= max_widening {
+            return None;
+        }
+        let parent = Self::new(self.addr, self.prefix - 1);
+        if parent == Self::new(other.addr, self.prefix - 1) {
+            Some(parent)
+        }
+        else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+fn max_prefix(addr: &IpAddr) -> u8 {
+    if addr.is_ipv4() {32} else {128}
+}
+
+/// Entry of an IPSet with its comment and the names it was resolved from, if any.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    network: Network,
+    comment: Option<String>,
+    sources: Vec<String>,
+    count: usize
+}
+
+impl Entry {
+    pub fn new(network: Network, source: Option<String>, comment: Option<String>) -> Self {
+        Self {
+            network,
+            comment,
+            sources: source.into_iter().collect(),
+            count: 1
+        }
+    }
+
+    fn absorb(&mut self, other: Entry) {
+        for source in other.sources {
+            if !self.sources.contains(&source) {
+                self.sources.push(source);
+            }
+        }
+        self.count += other.count;
+        self.comment = if self.sources.is_empty() {
+            None
+        }
+        else {
+            Some(format!("{} entries of {}", self.count, self.sources.join(", ")))
+        };
+    }
+}
+
+/// IPSet as rendered into firewall files and synchronized into kernel sets.
+pub struct IpSet {
+    name: String,
+    comment: String,
+    entries: Vec<Entry>
+}
+
+impl IpSet {
+    /// Builds the set, first collapsing its entries if configured.
+    pub fn new(name: String, comment: String, entries: Vec<Entry>, config: &IpsetConfig) -> Self {
+        let entries = if config.get_collapse() {
+            collapse(entries, config.get_max_widening())
+        }
+        else {
+            entries
+        };
+        Self {
+            name,
+            comment,
+            entries
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Networks of the set without any covered by another, as kernel interval sets require.
+    pub fn get_networks(&self) -> Vec<Network> {
+        collapse(self.entries.clone(), 0).into_iter().map(|e| e.network).collect()
+    }
+
+    /// Renders the `[IPSET]` section for a PVE firewall file.
+    pub fn render(&self) -> String {
+        let mut buf = format!("[IPSET {}] # {}\n\n", self.name, self.comment);
+        for entry in &self.entries {
+            match &entry.comment {
+                Some(comment) => buf += format!("{} # {comment}\n", entry.network).as_str(),
+                None => buf += format!("{}\n", entry.network).as_str()
+            }
+        }
+        buf += "\n";
+        buf
+    }
+}
+
+/// Drops entries covered by others, keeping the comment of the covering one, and merges adjacent
+/// halves into their covering network, as long as it is at most `max_widening` bits wider than a
+/// single address.
+fn collapse(mut entries: Vec<Entry>, max_widening: u8) -> Vec<Entry> {
+    entries.sort_by_key(|e| e.network);
+
+    let mut result: Vec<Entry> = Vec::new();
+    for entry in entries {
+        if result.last().is_some_and(|last| last.network.contains(&entry.network)) {
+            continue;
+        }
+        result.push(entry);
+
+        while result.len() >= 2 {
+            let len = result.len();
+            match result[len - 2].network.merge(&result[len - 1].network, max_widening) {
+                Some(parent) => {
+                    let last = result.pop().unwrap();
+                    let previous = result.last_mut().unwrap();
+                    previous.network = parent;
+                    previous.absorb(last);
+                },
+                None => break
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(network: &str, source: &str) -> Entry {
+        Entry::new(Network::parse(network).unwrap(), Some(source.to_string()), Some(source.to_string()))
+    }
+
+    fn rendered(entries: Vec<Entry>) -> Vec<String> {
+        entries.iter()
+            .map(|e| format!("{} # {}", e.network, e.comment.as_deref().unwrap_or("")))
+            .collect()
+    }
+
+    #[test]
+    fn drops_duplicate_and_covered_entries() {
+        let entries = vec![
+            entry("10.0.0.5", "b.test"),
+            entry("10.0.0.0/24", "a.test"),
+            entry("10.0.0.5", "c.test"),
+            entry("10.0.1.1", "a.test"),
+            entry("10.0.1.1", "b.test")
+        ];
+        assert_eq!(rendered(collapse(entries, 0)), ["10.0.0.0/24 # a.test", "10.0.1.1/32 # a.test"]);
+    }
+
+    #[test]
+    fn merges_halves_up_to_max_widening() {
+        let entries = || (0..6).map(|i| entry(&format!("192.0.2.{i}"), if i < 2 {"a.test"} else {"b.test"})).collect::<Vec<Entry>>();
+        assert_eq!(rendered(collapse(entries(), 0)).len(), 6);
+        assert_eq!(rendered(collapse(entries(), 1)),
+            ["192.0.2.0/31 # 2 entries of a.test", "192.0.2.2/31 # 2 entries of b.test", "192.0.2.4/31 # 2 entries of b.test"]);
+        assert_eq!(rendered(collapse(entries(), 2)),
+            ["192.0.2.0/30 # 4 entries of a.test, b.test", "192.0.2.4/31 # 2 entries of b.test"]);
+        assert_eq!(rendered(collapse(entries(), 8)),
+            ["192.0.2.0/30 # 4 entries of a.test, b.test", "192.0.2.4/31 # 2 entries of b.test"]);
+    }
+
+    #[test]
+    fn does_not_merge_across_boundaries() {
+        let entries = vec![entry("192.0.2.1", "a.test"), entry("192.0.2.2", "a.test")];
+        assert_eq!(rendered(collapse(entries, 8)), ["192.0.2.1/32 # a.test", "192.0.2.2/32 # a.test"]);
+    }
+
+    #[test]
+    fn orders_ipv4_before_ipv6() {
+        let entries = vec![
+            entry("2001:db8::1", "a.test"),
+            entry("192.0.2.1", "a.test"),
+            entry("2001:db8::", "a.test"),
+            entry("192.0.2.0", "a.test"),
+            entry("::", "b.test")
+        ];
+        assert_eq!(rendered(collapse(entries, 1)),
+            ["192.0.2.0/31 # 2 entries of a.test", "::/128 # b.test", "2001:db8::/127 # 2 entries of a.test"]);
+    }
+
+    #[test]
+    fn rejects_host_bits() {
+        assert_eq!(Network::parse("10.0.0.1/8"), Err("10.0.0.1/8 has host bits set, the network is 10.0.0.0/8".to_string()));
+        assert_eq!(Network::parse("fd00::/129"), Err("invalid prefix length 129".to_string()));
+    }
+}
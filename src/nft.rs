@@ -0,0 +1,182 @@
+use std::process::Command;
+
+use crate::{atomic_file, config::NftConfig, logging::{debug, error, info}, network::IpSet};
+
+/// Renders an `nft -f` batch replacing the content of the kernel sets backing the given IPSets.
+/// nft applies a batch as one transaction, so rules never see a half updated set. If `present`
+/// is given, sets not in it are left out, as a batch touching a missing set fails as a whole.
+pub fn render(ipsets: &[IpSet], config: &NftConfig, present: Option<&[String]>) -> String {
+    let table = config.get_table();
+    let mut buf = String::from("#!/usr/sbin/nft -f\n# generated by pve-dynamic-ipsets, changes will be overwritten\n\n");
+    for ipset in ipsets {
+        let networks = ipset.get_networks();
+        let mut rendered = false;
+        for (family, ipv4) in [(4, true), (6, false)] {
+            let set = config.get_set_name(family, ipset.get_name());
+            if present.is_some_and(|present| !present.contains(&set)) {
+                debug!("Kernel set {set} does not exist yet, leaving it to the next firewall compile");
+                continue;
+            }
+            let elements: Vec<String> = networks.iter()
+                .filter(|n| n.is_ipv4() == ipv4)
+                .map(|n| n.to_string())
+                .collect();
+            buf += format!("flush set {table} \"{set}\"\n").as_str();
+            if !elements.is_empty() {
+                buf += format!("add element {table} \"{set}\" {{ {} }}\n", elements.join(", ")).as_str();
+            }
+            rendered = true;
+        }
+        if rendered {
+            buf += "\n";
+        }
+    }
+    buf
+}
+
+/// Names of the sets currently in the configured table, according to `nft list sets`.
+fn list_sets(config: &NftConfig) -> Result<Vec<String>, String> {
+    let output = Command::new("nft").arg("list").arg("sets").output().map_err(|e| format!("Failed to run nft: {e}"))?;
+    if !output.status.success() {
+        return Err(format!("Listing kernel sets failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(parse_sets(&String::from_utf8_lossy(&output.stdout), config.get_table()))
+}
+
+/// Collects the set names listed under `table` in the output of `nft list sets`.
+fn parse_sets(listing: &str, table: &str) -> Vec<String> {
+    let mut sets: Vec<String> = Vec::new();
+    let mut in_table = false;
+    for line in listing.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix("table ") {
+            in_table = name.trim_end_matches('{').trim() == table;
+        }
+        else if let Some(name) = line.strip_prefix("set ").filter(|_| in_table) {
+            sets.push(name.trim_end_matches('{').trim().trim_matches('"').to_string());
+        }
+    }
+    sets
+}
+
+/// Writes the batch for the given IPSets to the configured script and, unless disabled, loads it
+/// with `nft -f`, leaving out the sets the firewall did not create yet. Does nothing if no script
+/// is configured.
+pub fn sync(ipsets: &[IpSet], config: &NftConfig) -> bool {
+    let Some(path) = config.get_script() else {
+        return true;
+    };
+    let present = if config.get_apply() {
+        match list_sets(config) {
+            Ok(present) => Some(present),
+            Err(e) => {
+                error!("{e}");
+                return false;
+            }
+        }
+    }
+    else {
+        None
+    };
+    if !atomic_file::write(path, &render(ipsets, config, present.as_deref())) {
+        error!("Failed to write nftables batch {path}");
+        return false;
+    }
+    if !config.get_apply() {
+        debug!("Wrote nftables batch {path} without applying it");
+        return true;
+    }
+
+    match Command::new("nft").arg("-f").arg(path).output() {
+        Ok(output) if output.status.success() => {
+            info!("Updated kernel sets of {} IPSets", ipsets.len());
+            true
+        },
+        Ok(output) => {
+            error!("Applying {path} failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            false
+        },
+        Err(e) => {
+            error!("Failed to run nft: {e}");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::{Config, IpsetConfig}, network::{Entry, Network}};
+
+    fn ipset(name: &str, networks: &[&str]) -> IpSet {
+        let entries = networks.iter()
+            .map(|n| Entry::new(Network::parse(n).unwrap(), None, None))
+            .collect();
+        IpSet::new(name.to_string(), String::new(), entries, &IpsetConfig::default())
+    }
+
+    fn ipsets() -> Vec<IpSet> {
+        vec![
+            ipset("domain_a_test", &["10.0.0.1", "fd00::1", "10.0.0.0/24", "10.0.1.1"]),
+            ipset("group_v4", &["192.0.2.0/24"])
+        ]
+    }
+
+    #[test]
+    fn renders_both_families_and_flushes_empty_ones() {
+        let config = Config::parse("[nftables]\nscript = \"/run/sets.nft\"").unwrap();
+        assert_eq!(render(&ipsets(), config.get_nftables(), None), "\
+#!/usr/sbin/nft -f
+# generated by pve-dynamic-ipsets, changes will be overwritten
+
+flush set inet proxmox-firewall \"v4-dc/domain_a_test\"
+add element inet proxmox-firewall \"v4-dc/domain_a_test\" { 10.0.0.0/24, 10.0.1.1/32 }
+flush set inet proxmox-firewall \"v6-dc/domain_a_test\"
+add element inet proxmox-firewall \"v6-dc/domain_a_test\" { fd00::1/128 }
+
+flush set inet proxmox-firewall \"v4-dc/group_v4\"
+add element inet proxmox-firewall \"v4-dc/group_v4\" { 192.0.2.0/24 }
+flush set inet proxmox-firewall \"v6-dc/group_v4\"
+
+");
+    }
+
+    #[test]
+    fn renders_set_format_and_skips_missing_sets() {
+        let config = Config::parse("[nftables]\ntable = \"ip filter\"\nset_format = \"{name}_{family}\"").unwrap();
+        let present = ["domain_a_test_6".to_string(), "group_v4_4".to_string()];
+        assert_eq!(render(&ipsets(), config.get_nftables(), Some(&present)), "\
+#!/usr/sbin/nft -f
+# generated by pve-dynamic-ipsets, changes will be overwritten
+
+flush set ip filter \"domain_a_test_6\"
+add element ip filter \"domain_a_test_6\" { fd00::1/128 }
+
+flush set ip filter \"group_v4_4\"
+add element ip filter \"group_v4_4\" { 192.0.2.0/24 }
+
+");
+    }
+
+    #[test]
+    fn lists_sets_of_the_table_only() {
+        let listing = "\
+table inet filter {
+\tset v4-dc/other {
+\t\ttype ipv4_addr
+\t}
+}
+table inet proxmox-firewall {
+\tset v4-dc/domain_a_test {
+\t\ttype ipv4_addr
+\t\tflags interval
+\t\telements = { 10.0.0.1 }
+\t}
+\tset \"v6-dc/domain_a_test\" {
+\t\ttype ipv6_addr
+\t}
+}
+";
+        assert_eq!(parse_sets(listing, "inet proxmox-firewall"), ["v4-dc/domain_a_test", "v6-dc/domain_a_test"]);
+    }
+}
@@ -1,24 +1,35 @@
-use std::{io::Write, fs::{self, File}, io::{BufRead, BufReader}, time::Instant};
+use std::{fs::{self, File}, io::{BufRead, BufReader}, time::Instant};
 
+use crate::logging::info;
+
+/// Static content of a target, read from `path` with any dynamic content stripped. While `path`
+/// does not exist, it is read from `fallback` instead, so the target file itself keeps its
+/// hand-written content until a static file is created.
 pub struct OrigCache {
     content: String,
     last_updated: Instant,
-    path: String
+    path: String,
+    fallback: String
 }
 
 impl OrigCache {
-    pub fn new(path: String) -> Self {
+    pub fn new(path: String, fallback: String) -> Self {
         let mut oc = Self {
             content: String::new(),
             last_updated: Instant::now(),
-            path
+            path,
+            fallback
         };
         oc.update();
         oc
     }
 
+    fn source(&self) -> &str {
+        if fs::metadata(&self.path).is_ok() {&self.path} else {&self.fallback}
+    }
+
     fn update(&mut self) -> bool  {
-        if let Ok(file) = File::open(&self.path) {
+        if let Ok(file) = File::open(self.source()) {
             let mut state = ReadState::Orig;
             let reader: BufReader<File> = BufReader::new(file);
             let mut buf = String::new();
@@ -39,7 +50,7 @@ impl OrigCache {
                             continue;
                         }
 
-                        if line == "" {
+                        if line.is_empty() {
                             if last_was_empty {
                                 continue;
                             }
@@ -54,8 +65,13 @@ impl OrigCache {
                     Err(_) => {return false;}
                 }
             }
+            // the separator written before the dynamic content is not part of the static content
+            buf.truncate(buf.trim_end_matches('\n').len());
+            if !buf.is_empty() {
+                buf.push('\n');
+            }
             self.content = buf;
-            println!("Updated origin file");
+            info!("Updated origin file from {}", self.source());
             self.mark_as_updated();
             return true;
         }
@@ -63,7 +79,7 @@ impl OrigCache {
     }
 
     pub fn try_update(&mut self) -> bool{
-        if let Ok(metadata) = fs::metadata(&self.path) {
+        if let Ok(metadata) = fs::metadata(self.source()) {
             if let Ok(modified) = metadata.modified() {
                 if let Ok(since_modif) = modified.elapsed() {
                     if since_modif < self.last_updated.elapsed() {
@@ -75,9 +91,8 @@ impl OrigCache {
         false
     }
 
-    pub fn write<T>(&self, mut writer: T) -> bool
-    where T: Write {
-        writer.write_all(self.content.as_bytes()).is_ok()
+    pub fn get_content(&self) -> &str {
+        &self.content
     }
 
     pub fn mark_as_updated(&mut self) {
@@ -1,125 +1,77 @@
-use std::{fs::{self, File, OpenOptions}, io::{BufRead, BufReader, BufWriter, Read, Write}, sync::mpsc::channel, thread::{self, sleep}, time::Duration};
+use std::{fs, sync::mpsc::channel, thread};
 
-use crate::{config::{Config, ProgramPath}, domain::Domain, domain_store::DomainStore, group::Group, module::Module, orig_cache::OrigCache};
+use crate::{atomic_file, config::{Config, ProgramPath}, diff, logging::{self, error, info}, module::Module, network::IpSet, nft, ruleset::Ruleset, target::{Target, TargetKind}};
 
 pub fn start(config: Config) -> Module<ProcessorSignal> {
     let (sender, receiver) = channel::<ProcessorSignal>();
 
     let handle = thread::spawn(move || {
-        let mut domains = DomainStore::new();
-        let mut groups: Vec<Group> = Vec::new();
-        let mut stat = OrigCache::new(config.get_path(ProgramPath::Static));
+        let mut config = config;
+        let mut targets = load_targets(&config);
+        let mut ruleset = Ruleset::load(&config);
         let mut first_run = true;
+        let mut ipset_names: Vec<String> = Vec::new();
+        let mut pending: Option<ProcessorSignal> = None;
 
-        if let Ok(dir) = fs::read_dir(config.get_path(ProgramPath::Directory)) {
-            for entry in dir {
-                if let Ok(sig) = receiver.try_recv() {
-                    match sig {
-                        ProcessorSignal::Stop => {return}
-                    }
-                }
+        info!("Initialization finished with {} targets, {} groups and {} domains", targets.len(), ruleset.groups_len(), ruleset.domains_len());
 
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if path.is_file() {
-                        if path.to_string_lossy().ends_with(".group") {
-                            let name = {
-                                let parts: Vec<String> = path.to_str().unwrap().split('/').map(|x|x.to_string()).collect();
-                                let parts: Vec<String> = parts[parts.len() - 1].split('.').map(|x|x.to_string()).collect();
-                                parts[0].clone()
-                            };
-                            println!("Loading group: {}", name);
-                            if let Some(group) = Group::read(name, path.to_string_lossy().to_string(), &mut domains) {
-                                groups.push(group);
-                            }
-                        }
-                        else if path.to_string_lossy().ends_with(".domains") {
-                            if let Ok(file) = File::open(&path) {
-                                println!("Loading domains from: {}", path.to_string_lossy());
-                                
-                                let reader = BufReader::new(file);
-                                for line in reader.lines() {
-                                    match line {
-                                        Ok(line) => {
-                                            if let Some(domain) = Domain::from_string(&line) {
-                                                domains.ingest_domain(domain);
-                                            }
-                                        }
-                                        Err(_) => {
-                                            break;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        else {
-                            println!("Skipping file {}", path.to_string_lossy());
+        loop {
+            let mut force = first_run;
+            if let Some(sig) = pending.take().or_else(|| receiver.try_recv().ok()) {
+                match sig {
+                    ProcessorSignal::Stop => {break},
+                    ProcessorSignal::Reload => {
+                        info!("Reloading configuration");
+                        match config.reload() {
+                            Ok(c) => {
+                                config = c;
+                                logging::set_level(config.get_log_level());
+                                targets = load_targets(&config);
+                                ruleset.reload(&config);
+                            },
+                            Err(e) => error!("Keeping previous configuration: {e}")
                         }
+                        force = true;
+                    },
+                    ProcessorSignal::Refresh => {
+                        ruleset.refresh();
+                        force = true;
                     }
                 }
             }
 
-            println!("Initialization finished with {} groups and {} domains", groups.len(), domains.len());
-
-            loop {
-                if let Ok(sig) = receiver.try_recv() {
-                    match sig {
-                        ProcessorSignal::Stop => {break}
-                    }
-                }
-                else if domains.update() > 0 || stat.try_update() || first_run {
-                    first_run = false;
-                    println!("Starting generation of dynamic content");
-                    if let Ok(file) = OpenOptions::new().create(true).truncate(true).write(true).open(config.get_path(ProgramPath::Generated)) {
-                        {
-                            let mut writer = BufWriter::new(file);
-                        
-                            stat.write(&mut writer);
-                            
-                            writeln!(&mut writer, "\n# DYNAMIC CONTENT BEGIN\n\n").unwrap();
-                            
-                            writer.write_all(domains.render().as_bytes()).unwrap();
-                            
-                            for group in &groups {
-                                writer.write_all(group.render(&domains).as_bytes()).unwrap();
-                            
-                            }
-                            
-                            writeln!(&mut writer, "\n# DYNAMIC CONTENT END\n\n").unwrap();
-                        }
-
-                        let propagation_result = {
-                            let mut buffer = String::new();
-                            if let Ok(mut src_file) = File::open(config.get_path(ProgramPath::Generated)) {
-                                if src_file.read_to_string(&mut buffer).is_ok() {
-                                    if let Ok(mut dst_file) = OpenOptions::new()
-                                    .create(true).truncate(true).write(true)
-                                    .open(config.get_path(ProgramPath::Original)) {
-                                        dst_file.write_all(buffer.as_bytes()).is_ok()
-                                    }
-                                    else {false}
-                                }
-                                else {false}
-                            }
-                            else {false}
-                        };
-
-                        if propagation_result {
-                            println!("Propagated dynamic content to origin file");
-                        }
-                        else {
-                            println!("Propagation failed");
-                        }
+            let reloaded = ruleset.try_reload(&config);
+            let domains_changed = ruleset.update() > 0;
+            let mut generated = false;
 
-                        stat.mark_as_updated();
-                    }
-                    else {
-                        println!("Failed to open file destination file for writing");
-                    }
+            // With kernel sets kept up to date directly, address changes need no firewall compile.
+            // The kernel sets only exist for IPSets in the firewall files though, so IPSets
+            // appearing or disappearing always regenerate the files.
+            let nftables = config.get_nftables();
+            let sync = nftables.get_script().is_some() && (domains_changed || reloaded || force);
+            let mut regenerate = domains_changed && (nftables.get_script().is_none() || nftables.get_update_targets());
+            if sync {
+                let names = all_ipset_names(&targets, &ruleset);
+                regenerate |= names != ipset_names;
+                ipset_names = names;
+            }
 
+            for target in &mut targets {
+                if target.try_update() || regenerate || reloaded || force {
+                    generated = true;
+                    info!("Starting generation of dynamic content for {}", target.get_name());
+                    let content = ruleset.render(target);
+                    generate(target, content);
                 }
-                else {
-                    sleep(Duration::from_secs(15));
+            }
+            if sync {
+                sync_kernel_sets(&config, &targets, &ruleset);
+            }
+            first_run = false;
+
+            if !generated {
+                if let Ok(sig) = receiver.recv_timeout(config.get_poll_interval()) {
+                    pending = Some(sig);
                 }
             }
         }
@@ -128,6 +80,136 @@ pub fn start(config: Config) -> Module<ProcessorSignal> {
     Module::new(handle, sender)
 }
 
+/// Loads and resolves everything once and writes the selected targets. With `dry_run`, a unified
+/// diff of each target against its current content is printed instead and 1 is returned if any
+/// target would change.
+pub fn apply(config: &Config, target: Option<&str>, dry_run: bool) -> i32 {
+    let mut targets = match select_targets(config, target) {
+        Ok(targets) => targets,
+        Err(e) => {
+            error!("{e}");
+            return 2;
+        }
+    };
+    let mut ruleset = Ruleset::load(config);
+    ruleset.update();
+
+    let mut result = 0;
+    for target in &mut targets {
+        let content = ruleset.render(target);
+        if dry_run {
+            let path = target.get_path(ProgramPath::Original);
+            let current = fs::read_to_string(&path).unwrap_or_default();
+            let diff = diff::unified(&current, &content, &path, &format!("{path} (generated)"));
+            if !diff.is_empty() {
+                result = 1;
+                print!("{diff}");
+            }
+        }
+        else if !generate(target, content) {
+            result = 1;
+        }
+    }
+    if !dry_run && !sync_kernel_sets(config, &targets, &ruleset) {
+        result = 1;
+    }
+    result
+}
+
+/// Loads and resolves everything once and prints the generated content of the selected targets.
+pub fn render(config: &Config, target: Option<&str>) -> i32 {
+    let targets = match select_targets(config, target) {
+        Ok(targets) => targets,
+        Err(e) => {
+            error!("{e}");
+            return 2;
+        }
+    };
+    let mut ruleset = Ruleset::load(config);
+    ruleset.update();
+
+    for target in &targets {
+        if targets.len() > 1 {
+            println!("# ==> {} <==", target.get_path(ProgramPath::Original));
+        }
+        print!("{}", ruleset.render(target));
+    }
+    0
+}
+
+/// Loads all files without resolving anything and reports whether all of them were valid.
+pub fn check(config: &Config) -> i32 {
+    let ruleset = Ruleset::load(config);
+    info!("Checked {} groups and {} domains, {} errors", ruleset.groups_len(), ruleset.domains_len(), ruleset.errors_len());
+    if ruleset.errors_len() > 0 {1} else {0}
+}
+
+/// Writes the addresses of the IPSets of all cluster targets into their kernel sets, if the
+/// nftables backend is configured. Runs after the targets were written, so new IPSets are
+/// already in the files the firewall creates their kernel sets from.
+fn sync_kernel_sets(config: &Config, targets: &[Target], ruleset: &Ruleset) -> bool {
+    let mut ipsets: Vec<IpSet> = Vec::new();
+    for target in targets.iter().filter(|t| t.get_kind() == TargetKind::Cluster) {
+        for ipset in ruleset.get_ipsets(target) {
+            if !ipsets.iter().any(|i| i.get_name() == ipset.get_name()) {
+                ipsets.push(ipset);
+            }
+        }
+    }
+    nft::sync(&ipsets, config.get_nftables())
+}
+
+/// Sorted names of the IPSets rendered into any of the targets.
+fn all_ipset_names(targets: &[Target], ruleset: &Ruleset) -> Vec<String> {
+    let mut names: Vec<String> = targets.iter()
+        .flat_map(|t| ruleset.get_ipsets(t))
+        .map(|ipset| ipset.get_name().to_string())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn load_targets(config: &Config) -> Vec<Target> {
+    config.get_targets().iter()
+        .map(|t| Target::new(t.clone(), config.get_directory()))
+        .collect()
+}
+
+fn select_targets(config: &Config, name: Option<&str>) -> Result<Vec<Target>, String> {
+    match name {
+        Some(name) => {
+            let target = config.get_targets().iter()
+                .find(|t| t.get_name() == name)
+                .ok_or(format!("Unknown target {name}"))?;
+            Ok(vec![Target::new(target.clone(), config.get_directory())])
+        },
+        None => Ok(load_targets(config))
+    }
+}
+
+fn generate(target: &mut Target, content: String) -> bool {
+    if !atomic_file::write(&target.get_path(ProgramPath::Generated), &content) {
+        error!("Failed to write generated content for {}", target.get_name());
+        return false;
+    }
+
+    let propagated = atomic_file::write(&target.get_path(ProgramPath::Original), &content);
+    if propagated {
+        info!("Propagated dynamic content to {}", target.get_path(ProgramPath::Original));
+    }
+    else {
+        error!("Propagation to {} failed", target.get_path(ProgramPath::Original));
+    }
+
+    target.mark_as_updated();
+    propagated
+}
+
 pub enum ProcessorSignal {
-    Stop
-}
\ No newline at end of file
+    Stop,
+    /// Reload the configuration file and all `.group` and `.domains` files.
+    Reload,
+    /// Re-resolve all domains and regenerate all targets right away.
+    Refresh
+}
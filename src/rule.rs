@@ -1,72 +1,413 @@
-use crate::domain::Domain;
+use std::fmt;
 
+use crate::{diagnostic::{tokenize, ParseError}, network::Network};
+
+/// Rule template of a group, rendered once per IPSet of the group. Written in PVE rule syntax
+/// without the address option, e.g. `|OUT HTTPS(ACCEPT) -i net0 -log info # comment`, or as
+/// direction, action, optional macro or protocol and log level followed by optional `-key value`
+/// fields in any order. The side matching the direction (`-source` for `IN`, `-dest` for `OUT`)
+/// is bound to the group's sets, the other one may be given as an [`Endpoint`]. `FORWARD` rules
+/// bind `-dest` unless it is given, in which case they bind `-source`.
 #[derive(PartialEq)]
 pub struct DynRule {
+    enabled: bool,
     direction: Direction,
     action: Action,
-    protocol: String,
-    logging: LogLevel
+    service: Option<Service>,
+    logging: LogLevel,
+    fields: RuleFields,
+    bound: Side,
+    opposite: Option<Endpoint>,
+    comment: Option<String>
+}
+
+/// Endpoint of a rule opposite to the group's sets: a static IPSet or alias reference or a list
+/// of networks as written, or all sets of another group, written `group:<name>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Static(String),
+    Group(String)
+}
+
+impl Endpoint {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if let Some(name) = s.strip_prefix("group:") {
+            return match is_name(name, "-_") {
+                true => Ok(Self::Group(name.to_string())),
+                false => Err(format!("invalid group name {name}"))
+            };
+        }
+        let reference = s.strip_prefix('+').unwrap_or(s);
+        let name = reference.strip_prefix("dc/").or(reference.strip_prefix("guest/")).unwrap_or(reference);
+        if is_name(name, "-_") && name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Ok(Self::Static(s.to_string()));
+        }
+        for network in s.split(',') {
+            Network::parse(network)?;
+        }
+        Ok(Self::Static(s.to_string()))
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Static(endpoint) => f.write_str(endpoint),
+            Self::Group(name) => write!(f, "group:{name}")
+        }
+    }
+}
+
+/// What a rule matches besides addresses: a PVE macro, rendered as `HTTPS(ACCEPT)`, or a raw
+/// protocol, rendered as `ACCEPT -p tcp`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Service {
+    Macro(&'static str),
+    Protocol(String)
+}
+
+impl Service {
+    /// Matches PVE macros case-insensitively, then protocol names and numbers.
+    pub fn from_string(s: &str) -> Option<Self> {
+        if let Some(name) = MACROS.iter().find(|m| m.eq_ignore_ascii_case(s)) {
+            return Some(Self::Macro(name));
+        }
+        Self::protocol(s)
+    }
+
+    pub fn protocol(s: &str) -> Option<Self> {
+        let s = s.to_ascii_lowercase();
+        if PROTOCOLS.contains(&s.as_str()) || s.parse::<u8>().is_ok() {
+            Some(Self::Protocol(s))
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// Optional fields of a dynamic rule, rendered as the PVE rule options of the same name.
+#[derive(Default, PartialEq)]
+struct RuleFields {
+    iface: Option<String>,
+    proto: Option<Service>,
+    dport: Option<String>,
+    sport: Option<String>,
+    icmp_type: Option<String>,
+    log: Option<LogLevel>,
+    source: Option<Endpoint>,
+    dest: Option<Endpoint>
 }
 
 impl DynRule {
-    pub fn from_string(s: String) -> Option<Self> {
-        let parts: Vec<String> = s.split(' ').map(|x|x.to_string()).collect();
-        if parts.len() == 4 {
-            if let Some(direction) = Direction::from_string(&parts[0]) {
-                if let Some(action) = Action::from_string(&parts[1]) {
-                    if let Some(logging) = LogLevel::from_string(&parts[3]) {
-                        return Some(Self {
-                            direction,
-                            action,
-                            protocol: parts[2].clone(),
-                            logging
-                        });
-                    }
-                }
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        // Blanking the markers instead of cutting them keeps the token columns intact.
+        let enabled = !s.trim_start().starts_with('|');
+        let mut line = if enabled {s.to_string()} else {s.replacen('|', " ", 1)};
+        let comment = match line.split_once('#') {
+            Some((body, comment)) => {
+                let comment = comment.trim().to_string();
+                line = body.to_string();
+                Some(comment).filter(|c| !c.is_empty())
+            },
+            None => None
+        };
+        let end = line.trim_end().chars().count() + 1;
+
+        let tokens = tokenize(&line);
+        let mut positional: Vec<(usize, &str)> = Vec::new();
+        let mut fields = RuleFields::default();
+        // Column of each field, to point errors about combinations of fields at the right one.
+        let mut columns: Vec<(&str, usize)> = Vec::new();
+        let column_of = |columns: &[(&str, usize)], keys: &[&str]| -> usize {
+            columns.iter().find(|(key, _)| keys.contains(key)).map_or(1, |(_, column)| *column)
+        };
+
+        let mut iter = tokens.iter();
+        while let Some((column, token)) = iter.next() {
+            if !token.starts_with('-') || token.len() < 2 {
+                positional.push((*column, token));
+                continue;
             }
+            if !RuleFields::KEYS.contains(&&token[1..]) {
+                return Err(ParseError::new(*column, format!("unknown field {token}")));
+            }
+            let (value_column, value) = iter.next()
+                .ok_or(ParseError::new(end, format!("missing value for {token}")))?;
+            fields.set(&token[1..], value).map_err(|e| ParseError::new(*value_column, e))?;
+            columns.push((&token[1..], *column));
+        }
+
+        if positional.len() < 2 {
+            return Err(ParseError::new(end, "expected direction and action".to_string()));
+        }
+        let direction = Direction::from_string(positional[0].1)
+            .ok_or(ParseError::new(positional[0].0, format!("unknown direction {}", positional[0].1)))?;
+
+        // Either PVE's `MACRO(ACTION)` or the action followed by an optional macro or protocol.
+        let (column, token) = positional[1];
+        let (action, mut service) = match token.strip_suffix(')').and_then(|t| t.split_once('(')) {
+            Some((name, action)) => {
+                let service = Service::from_string(name)
+                    .filter(|s| matches!(s, Service::Macro(_)))
+                    .ok_or(ParseError::new(column, format!("unknown macro {name}")))?;
+                (action, Some(service))
+            },
+            None => (token, None)
+        };
+        let action = Action::from_string(action)
+            .ok_or(ParseError::new(column, format!("unknown action {action}")))?;
+
+        let mut logging = None;
+        for (column, token) in positional.iter().skip(2) {
+            if logging.is_some() {
+                return Err(ParseError::new(*column, format!("unexpected {token} after log level")));
+            }
+            if let Some(level) = LogLevel::from_string(token) {
+                logging = Some(level);
+            }
+            else if service.is_none() {
+                service = Some(Service::from_string(token)
+                    .ok_or(ParseError::new(*column, format!("unknown macro or protocol {token}")))?);
+            }
+            else {
+                return Err(ParseError::new(*column, format!("expected log level, found {token}")));
+            }
+        }
+
+        let service = match (service, fields.proto.take()) {
+            (Some(_), Some(_)) => return Err(ParseError::new(column_of(&columns, &["p", "proto"]), "protocol given both positionally and by -p".to_string())),
+            (service, proto) => service.or(proto)
+        };
+        let logging = match (logging, fields.log.take()) {
+            (Some(_), Some(_)) => return Err(ParseError::new(column_of(&columns, &["log"]), "log level given both positionally and by -log".to_string())),
+            (logging, log) => logging.or(log).unwrap_or(LogLevel::NoLog)
+        };
+
+        let bound = match direction {
+            Direction::In => Side::Source,
+            Direction::Out => Side::Dest,
+            Direction::Forward if fields.dest.is_some() => Side::Source,
+            Direction::Forward => Side::Dest
+        };
+        let (own, opposite) = match bound {
+            Side::Source => (fields.source.take(), fields.dest.take()),
+            Side::Dest => (fields.dest.take(), fields.source.take())
+        };
+        if own.is_some() {
+            let message = match direction {
+                Direction::Forward => "-source and -dest of FORWARD rules cannot both be given, one is the group's own set".to_string(),
+                _ => format!("-{bound} of {direction} rules is the group's own set")
+            };
+            return Err(ParseError::new(column_of(&columns, &[bound.to_string().as_str()]), message));
+        }
+
+        if service.is_none() && (fields.dport.is_some() || fields.sport.is_some()) {
+            return Err(ParseError::new(column_of(&columns, &["dport", "sport"]), "ports require a macro or protocol".to_string()));
+        }
+        if fields.icmp_type.is_some() && !matches!(&service, Some(Service::Protocol(p)) if ["icmp", "ipv6-icmp", "icmpv6"].contains(&p.as_str())) {
+            return Err(ParseError::new(column_of(&columns, &["icmp-type"]), "-icmp-type requires protocol icmp or ipv6-icmp".to_string()));
         }
-        None
+
+        Ok(Self {
+            enabled,
+            direction,
+            action,
+            service,
+            logging,
+            fields,
+            bound,
+            opposite,
+            comment
+        })
+    }
+
+    pub fn get_direction(&self) -> &Direction {
+        &self.direction
+    }
+
+    pub fn get_opposite(&self) -> Option<&Endpoint> {
+        self.opposite.as_ref()
     }
 
-    pub fn render(&self, domain: &Domain) -> String {
-        format!("{} {}({}) -{} +dc/domain_{} -log {}\n",
-            self.direction.to_string(),
-            self.protocol,
-            self.action.to_string(),
-            self.direction.get_flag(),
-            domain.get_name(),
-            self.logging.to_string()
-        )
+    /// Renders the rule against the IPSet `ipset` of the given scope, with `opposite` standing in
+    /// for the opposite endpoint, and appends `origin` to its comment.
+    pub fn render(&self, ipset: &str, opposite: Option<&str>, scope: &str, origin: &str) -> String {
+        let mut buf = String::new();
+        self.write(&mut buf, Some(&format!("+{scope}/{ipset}")), opposite, Some(origin)).unwrap();
+        buf += "\n";
+        buf
     }
+
+    /// Writes the rule in PVE syntax, with the address bound to the group only if `ipset` is given.
+    fn write(&self, f: &mut impl fmt::Write, ipset: Option<&str>, opposite: Option<&str>, origin: Option<&str>) -> fmt::Result {
+        if !self.enabled {
+            f.write_char('|')?;
+        }
+        match &self.service {
+            Some(Service::Macro(name)) => write!(f, "{} {name}({})", self.direction, self.action)?,
+            _ => write!(f, "{} {}", self.direction, self.action)?
+        }
+        if let Some(iface) = &self.fields.iface {
+            write!(f, " -i {iface}")?;
+        }
+        let (source, dest) = match self.bound {
+            Side::Source => (ipset, opposite),
+            Side::Dest => (opposite, ipset)
+        };
+        if let Some(source) = source {
+            write!(f, " -source {source}")?;
+        }
+        if let Some(dest) = dest {
+            write!(f, " -dest {dest}")?;
+        }
+        if let Some(Service::Protocol(proto)) = &self.service {
+            write!(f, " -p {proto}")?;
+        }
+        if let Some(dport) = &self.fields.dport {
+            write!(f, " -dport {dport}")?;
+        }
+        if let Some(sport) = &self.fields.sport {
+            write!(f, " -sport {sport}")?;
+        }
+        write!(f, " -log {}", self.logging)?;
+        if let Some(icmp_type) = &self.fields.icmp_type {
+            write!(f, " -icmp-type {icmp_type}")?;
+        }
+        match (&self.comment, origin) {
+            (Some(comment), Some(origin)) => write!(f, " # {comment}, {origin}"),
+            (Some(comment), None) => write!(f, " # {comment}"),
+            (None, Some(origin)) => write!(f, " # {origin}"),
+            (None, None) => Ok(())
+        }
+    }
+}
+
+/// Renders the rule template as written in PVE syntax, which parses back to the same rule.
+impl fmt::Display for DynRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let opposite = self.opposite.as_ref().map(|o| o.to_string());
+        self.write(f, None, opposite.as_deref(), None)
+    }
+}
+
+impl RuleFields {
+    const KEYS: [&'static str; 10] = ["i", "iface", "p", "proto", "dport", "sport", "icmp-type", "log", "source", "dest"];
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        if key == "source" || key == "dest" {
+            let field = if key == "source" {&mut self.source} else {&mut self.dest};
+            if field.is_some() {
+                return Err(format!("-{key} given more than once"));
+            }
+            *field = Some(Endpoint::parse(value)?);
+            return Ok(());
+        }
+        if key == "log" {
+            if self.log.is_some() {
+                return Err("-log given more than once".to_string());
+            }
+            self.log = Some(LogLevel::from_string(value).ok_or(format!("unknown log level {value}"))?);
+            return Ok(());
+        }
+        if matches!(key, "p" | "proto") {
+            if self.proto.is_some() {
+                return Err(format!("-{key} given more than once"));
+            }
+            self.proto = Some(Service::protocol(value).ok_or(format!("unknown protocol {value}"))?);
+            return Ok(());
+        }
+        let (field, valid) = match key {
+            "i" | "iface" => (&mut self.iface, is_name(value, ".-_")),
+            "dport" => (&mut self.dport, is_port_list(value)),
+            "sport" => (&mut self.sport, is_port_list(value)),
+            "icmp-type" => (&mut self.icmp_type, is_name(value, "-")),
+            _ => return Err(format!("unknown field -{key}"))
+        };
+        if !valid {
+            return Err(format!("invalid value {value} for -{key}"));
+        }
+        if field.is_some() {
+            return Err(format!("-{key} given more than once"));
+        }
+        *field = Some(value.to_ascii_lowercase());
+        Ok(())
+    }
+}
+
+/// Macros shipped with pve-firewall.
+const MACROS: [&str; 87] = [
+    "Amanda", "Auth", "BGP", "BitTorrent", "BitTorrent32", "CVS", "Ceph", "Citrix", "DAAP", "DCC",
+    "DHCPfwd", "DHCPv6", "DNS", "Distcc", "Edonkey", "FTP", "Finger", "GNUnet", "GRE", "Git",
+    "HKP", "HTTP", "HTTPS", "ICPV2", "ICQ", "IMAP", "IMAPS", "IPIP", "IPsec", "IPsecah",
+    "IPsecnat", "IRC", "Jetdirect", "L2TP", "LDAP", "LDAPS", "MDNS", "MSNP", "MSSQL", "Mail",
+    "Munin", "MySQL", "NNTP", "NNTPS", "NTP", "NeighborDiscovery", "OSPF", "OpenVPN", "PCA", "PMG",
+    "POP3", "POP3S", "PPtP", "Ping", "PostgreSQL", "Printer", "RDP", "RIP", "RNDC", "Razor",
+    "Rdate", "Rsync", "SANE", "SMB", "SMBswat", "SMTP", "SMTPS", "SNMP", "SPAMD", "SPICEproxy",
+    "SSH", "SVN", "SixXS", "Squid", "Submission", "Syslog", "TFTP", "Telnet", "Telnets", "Time",
+    "Trcrt", "VNC", "VNCL", "Web", "Webcache", "Webmin", "Whois"
+];
+
+/// Protocol names accepted by `-p`, besides protocol numbers.
+const PROTOCOLS: [&str; 18] = [
+    "tcp", "udp", "udplite", "sctp", "dccp", "icmp", "ipv6-icmp", "icmpv6", "igmp",
+    "gre", "esp", "ah", "ipencap", "ipip", "ospf", "vrrp", "pim", "l2tp"
+];
+
+fn is_name(s: &str, extra: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || extra.contains(c))
+}
+
+/// Accepts PVE port lists: comma separated ports, service names or `from:to` ranges.
+fn is_port_list(s: &str) -> bool {
+    s.split(',').all(|item| {
+        let parts: Vec<&str> = item.split(':').collect();
+        parts.len() <= 2 && parts.iter().all(|p| p.parse::<u16>().is_ok() || (is_name(p, "-") && !p.starts_with(|c: char| c.is_ascii_digit())))
+    })
 }
 
 #[derive(PartialEq)]
 pub enum Direction {
     In,
-    Out
+    Out,
+    /// Routed traffic, filtered by the cluster, host and VNet firewalls
+    Forward
 }
 
 impl Direction {
-    pub fn from_string(s: &String) -> Option<Self> {
+    pub fn from_string(s: &str) -> Option<Self> {
         match s.to_ascii_lowercase().as_str() {
             "in" => Some(Self::In),
             "out" => Some(Self::Out),
+            "forward" | "fwd" => Some(Self::Forward),
             _ => None
         }
     }
+}
 
-    pub fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             Self::In => "IN",
             Self::Out => "OUT",
-        }.to_string()
+            Self::Forward => "FORWARD"
+        })
     }
+}
 
-    pub fn get_flag(&self) -> String {
-        match self {
-            Self::In => "source",
-            Self::Out => "dest",
-        }.to_string()
+/// Address option of a rule bound to the group's sets.
+#[derive(PartialEq)]
+enum Side {
+    Source,
+    Dest
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Source => "source",
+            Self::Dest => "dest"
+        })
     }
 }
 
@@ -78,7 +419,7 @@ pub enum Action {
 }
 
 impl Action {
-    pub fn from_string(s: &String) -> Option<Self> {
+    pub fn from_string(s: &str) -> Option<Self> {
         match s.to_ascii_lowercase().as_str() {
             "accept" => Some(Self::Accept),
             "drop" => Some(Self::Drop),
@@ -86,13 +427,15 @@ impl Action {
             _ => None
         }
     }
+}
 
-    pub fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             Self::Accept => "ACCEPT",
             Self::Drop => "DROP",
             Self::Reject => "REJECT"
-        }.to_string()
+        })
     }
 }
 
@@ -110,7 +453,7 @@ pub enum LogLevel {
 }
 
 impl LogLevel {
-    pub fn from_string(s: &String) -> Option<Self> {
+    pub fn from_string(s: &str) -> Option<Self> {
         match s.to_ascii_lowercase().as_str() {
             "nolog" | "none" => Some(Self::NoLog),
             "emergency" | "emerg" => Some(Self::Emergency),
@@ -124,9 +467,11 @@ impl LogLevel {
             _ => None
         }
     }
+}
 
-    pub fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             LogLevel::NoLog => "nolog",
             LogLevel::Emergency => "emerg",
             LogLevel::Alert => "alert",
@@ -136,6 +481,68 @@ impl LogLevel {
             LogLevel::Notice => "notice",
             LogLevel::Info => "info",
             LogLevel::Debug => "debug"
-        }.to_string()
+        })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `s`, checks it is displayed as `expected` and that this parses back to the same rule.
+    fn round_trip(s: &str, expected: &str) -> DynRule {
+        let rule = DynRule::parse(s).unwrap();
+        assert_eq!(rule.to_string(), expected);
+        assert!(DynRule::parse(expected).unwrap() == rule, "{expected} parsed differently");
+        rule
+    }
+
+    #[test]
+    fn round_trips_disabled_rule_with_comment() {
+        let rule = round_trip("|OUT HTTPS(ACCEPT) -i net0 -log info # web, not yet", "|OUT HTTPS(ACCEPT) -i net0 -log info # web, not yet");
+        assert_eq!(rule.render("domain_a_test", None, "dc", "a.test from web.group"),
+            "|OUT HTTPS(ACCEPT) -i net0 -dest +dc/domain_a_test -log info # web, not yet, a.test from web.group\n");
+    }
+
+    #[test]
+    fn round_trips_macro_and_protocol() {
+        round_trip("in accept ssh warning", "IN SSH(ACCEPT) -log warning");
+        round_trip("IN ACCEPT tcp -dport 22,2222", "IN ACCEPT -p tcp -dport 22,2222 -log nolog");
+        round_trip("OUT ACCEPT -proto UDP -sport 1024:65535 -log nolog", "OUT ACCEPT -p udp -sport 1024:65535 -log nolog");
+        round_trip("IN ACCEPT -p icmp -icmp-type echo-request", "IN ACCEPT -p icmp -log nolog -icmp-type echo-request");
+    }
+
+    #[test]
+    fn binds_forward_rules_to_the_side_not_given() {
+        let rule = round_trip("FORWARD ACCEPT -source +dc/admins", "FORWARD ACCEPT -source +dc/admins -log nolog");
+        assert_eq!(rule.render("domain_a_test", Some("+dc/admins"), "dc", "a.test"),
+            "FORWARD ACCEPT -source +dc/admins -dest +dc/domain_a_test -log nolog # a.test\n");
+
+        let rule = round_trip("FORWARD DROP -dest 10.0.0.0/8,192.168.0.0/16", "FORWARD DROP -dest 10.0.0.0/8,192.168.0.0/16 -log nolog");
+        assert_eq!(rule.render("domain_a_test", Some("10.0.0.0/8,192.168.0.0/16"), "dc", "a.test"),
+            "FORWARD DROP -source +dc/domain_a_test -dest 10.0.0.0/8,192.168.0.0/16 -log nolog # a.test\n");
+    }
+
+    #[test]
+    fn round_trips_group_endpoint() {
+        let rule = round_trip("IN SSH(ACCEPT) -dest group:admins", "IN SSH(ACCEPT) -dest group:admins -log nolog");
+        assert_eq!(rule.get_opposite(), Some(&Endpoint::Group("admins".to_string())));
+        assert_eq!(error("IN ACCEPT -dest group:bad.name"), ParseError::new(17, "invalid group name bad.name".to_string()));
+    }
+
+    fn error(s: &str) -> ParseError {
+        DynRule::parse(s).err().unwrap()
+    }
+
+    #[test]
+    fn points_at_conflicting_field() {
+        assert_eq!(error("IN ACCEPT -log info -source 10.0.0.0/8"),
+            ParseError::new(21, "-source of IN rules is the group's own set".to_string()));
+        assert_eq!(error("FORWARD ACCEPT -dest +dc/a -source +dc/b"),
+            ParseError::new(28, "-source and -dest of FORWARD rules cannot both be given, one is the group's own set".to_string()));
+        assert_eq!(error("OUT ACCEPT -i net0 -dport 443"),
+            ParseError::new(20, "ports require a macro or protocol".to_string()));
+        assert_eq!(error("OUT ACCEPT -p tcp -icmp-type echo-request"),
+            ParseError::new(19, "-icmp-type requires protocol icmp or ipv6-icmp".to_string()));
+    }
+}
@@ -0,0 +1,225 @@
+use std::{collections::HashMap, fs::{self, File}, io::{BufRead, BufReader}, path::Path, time::SystemTime};
+
+use crate::{config::Config, diagnostic::Diagnostic, domain::Domain, domain_store::DomainStore, group::Group, logging::{debug, error, info}, network::IpSet, target::{Target, TargetKind}};
+
+/// Everything loaded from the configuration directory: the shared domain store plus the groups and
+/// `.domains` files referencing it.
+pub struct Ruleset {
+    domains: DomainStore,
+    groups: Vec<Group>,
+    domain_files: HashMap<String, Vec<String>>,
+    sources: HashMap<String, SystemTime>,
+    errors: usize
+}
+
+impl Ruleset {
+    pub fn load(config: &Config) -> Self {
+        let mut domains = DomainStore::new(config.get_dns().clone(), config.get_ipsets().clone());
+        let mut groups: Vec<Group> = Vec::new();
+        let mut domain_files: HashMap<String, Vec<String>> = HashMap::new();
+        let mut errors: usize = 0;
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        let mut sources: HashMap<String, SystemTime> = HashMap::new();
+
+        if let Ok(dir) = fs::read_dir(config.get_directory()) {
+            for entry in dir.flatten() {
+                let path = entry.path();
+                if path.is_file() {
+                    if let Some(modified) = source_modified(&path) {
+                        sources.insert(path.to_string_lossy().to_string(), modified);
+                    }
+                    let name = {
+                        let parts: Vec<String> = path.to_str().unwrap().split('/').map(|x|x.to_string()).collect();
+                        let parts: Vec<String> = parts[parts.len() - 1].split('.').map(|x|x.to_string()).collect();
+                        parts[0].clone()
+                    };
+                    if path.to_string_lossy().ends_with(".group") {
+                        info!("Loading group: {}", name);
+                        if let Some(group) = Group::read(name, path.to_string_lossy().to_string(), &mut domains, &mut diagnostics) {
+                            groups.push(group);
+                        }
+                        else {
+                            error!("Failed to load group from {}", path.to_string_lossy());
+                            errors += 1;
+                        }
+                    }
+                    else if path.to_string_lossy().ends_with(".domains") {
+                        if let Ok(file) = File::open(&path) {
+                            info!("Loading domains from: {}", path.to_string_lossy());
+
+                            let mut fqdns: Vec<String> = Vec::new();
+                            let reader = BufReader::new(file);
+                            for (idx, line) in reader.lines().enumerate() {
+                                match line {
+                                    Ok(line) => {
+                                        if line.trim().is_empty() {
+                                            continue;
+                                        }
+                                        match Domain::parse(&line) {
+                                            Ok(domain) => {
+                                                fqdns.push(domain.get_fqdn());
+                                                domains.ingest_domain(domain);
+                                            },
+                                            Err(e) => diagnostics.push(e.locate(&path.to_string_lossy(), idx + 1))
+                                        }
+                                    }
+                                    Err(_) => {
+                                        error!("Failed to read {}", path.to_string_lossy());
+                                        errors += 1;
+                                        break;
+                                    }
+                                }
+                            }
+                            domain_files.insert(name, fqdns);
+                        }
+                        else {
+                            error!("Failed to open {}", path.to_string_lossy());
+                            errors += 1;
+                        }
+                    }
+                    else {
+                        debug!("Skipping file {}", path.to_string_lossy());
+                    }
+                }
+            }
+        }
+        else {
+            error!("Failed to read directory {}", config.get_directory());
+            errors += 1;
+        }
+
+        for diagnostic in &diagnostics {
+            error!("{diagnostic}");
+        }
+        errors += diagnostics.len();
+
+        groups.sort_by_key(|g| g.get_name());
+        for group in &groups {
+            for name in group.get_referenced_groups() {
+                if !groups.iter().any(|g| g.get_name() == name) {
+                    error!("Group {} references unknown group {name}", group.get_name());
+                    errors += 1;
+                }
+            }
+            for name in group.get_attached_targets() {
+                if !config.get_targets().iter().any(|t| t.get_name() == name) {
+                    error!("Group {} is attached to unknown target {name}", group.get_name());
+                    errors += 1;
+                }
+            }
+            if !group.get_attached_targets().is_empty() && !rendered_by_cluster(config, &group.get_name()) {
+                error!("Group {} is attached to targets, but no cluster target renders [group {}]", group.get_name(), group.get_name());
+                errors += 1;
+            }
+        }
+
+        for target in config.get_targets().iter().filter(|t| matches!(t.get_kind(), TargetKind::Host | TargetKind::VNet)) {
+            for group in groups.iter().filter(|g| target.uses_group(&g.get_name())) {
+                if !rendered_by_cluster(config, &group.get_name()) {
+                    error!("Target {} uses group {}, but no cluster target renders its IPSets", target.get_name(), group.get_name());
+                    errors += 1;
+                }
+            }
+        }
+
+        Self {
+            domains,
+            groups,
+            domain_files,
+            sources,
+            errors
+        }
+    }
+
+    /// Reloads all `.group` and `.domains` files if any of them was added, modified or deleted since
+    /// they were last loaded. Domains still referenced afterwards keep their resolved addresses.
+    pub fn try_reload(&mut self, config: &Config) -> bool {
+        let mut sources: HashMap<String, SystemTime> = HashMap::new();
+        if let Ok(dir) = fs::read_dir(config.get_directory()) {
+            for entry in dir.flatten() {
+                if let Some(modified) = source_modified(&entry.path()) {
+                    sources.insert(entry.path().to_string_lossy().to_string(), modified);
+                }
+            }
+        }
+
+        if sources == self.sources {
+            return false;
+        }
+
+        for (path, modified) in &sources {
+            match self.sources.get(path) {
+                None => info!("Detected new file {path}"),
+                Some(previous) if previous != modified => info!("Detected change of {path}"),
+                _ => {}
+            }
+        }
+        for path in self.sources.keys() {
+            if !sources.contains_key(path) {
+                info!("Detected removal of {path}");
+            }
+        }
+
+        self.reload(config);
+        true
+    }
+
+    /// Loads all files again, keeping the resolved addresses of domains that are still referenced.
+    pub fn reload(&mut self, config: &Config) {
+        let fresh = Self::load(config);
+        let changed = self.domains.reconcile(fresh.domains);
+        self.groups = fresh.groups;
+        self.domain_files = fresh.domain_files;
+        self.sources = fresh.sources;
+        self.errors = fresh.errors;
+
+        info!("Reloaded {} groups and {} domains, {changed} domains added or removed", self.groups.len(), self.domains.len());
+    }
+
+    /// Re-resolves every domain whose interval elapsed, returning the number of changed domains.
+    pub fn update(&mut self) -> usize {
+        self.domains.update()
+    }
+
+    /// Re-resolves every domain regardless of its interval.
+    pub fn refresh(&mut self) -> usize {
+        self.domains.refresh()
+    }
+
+    pub fn render(&self, target: &Target) -> String {
+        target.render(&self.domains, &self.groups, &self.domain_files)
+    }
+
+    pub fn get_ipsets(&self, target: &Target) -> Vec<IpSet> {
+        target.get_ipsets(&self.domains, &self.groups, &self.domain_files)
+    }
+
+    pub fn groups_len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn domains_len(&self) -> usize {
+        self.domains.len()
+    }
+
+    /// Number of files and lines that could not be loaded, each of them already logged.
+    pub fn errors_len(&self) -> usize {
+        self.errors
+    }
+}
+
+/// Whether a cluster target renders the group, which `GROUP` references and the `+dc/` sets used
+/// by other targets rely on.
+fn rendered_by_cluster(config: &Config, name: &String) -> bool {
+    config.get_targets().iter().any(|t| t.get_kind() == TargetKind::Cluster && t.uses_group(name))
+}
+
+fn source_modified(path: &Path) -> Option<SystemTime> {
+    let name = path.to_string_lossy();
+    if path.is_file() && (name.ends_with(".group") || name.ends_with(".domains")) {
+        fs::metadata(path).ok()?.modified().ok()
+    }
+    else {
+        None
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use crate::{config::{ProgramPath, TargetConfig}, domain_store::DomainStore, group::Group, network::IpSet, orig_cache::OrigCache, rule::Direction};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetKind {
+    Cluster,
+    Host,
+    Guest,
+    /// SDN VNet firewall, `/etc/pve/sdn/firewall/<vnet>.fw`
+    VNet
+}
+
+impl TargetKind {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "cluster" | "dc" => Some(Self::Cluster),
+            "host" | "node" => Some(Self::Host),
+            "guest" | "vm" | "ct" => Some(Self::Guest),
+            "vnet" | "sdn" => Some(Self::VNet),
+            _ => None
+        }
+    }
+
+    pub fn detect(file: &str) -> Option<Self> {
+        let filename = file.rsplit('/').next()?;
+        let stem = filename.strip_suffix(".fw")?;
+        if file.trim_end_matches(filename).ends_with("sdn/firewall/") && !stem.is_empty() {
+            Some(Self::VNet)
+        }
+        else if stem == "cluster" {
+            Some(Self::Cluster)
+        }
+        else if stem == "host" {
+            Some(Self::Host)
+        }
+        else if !stem.is_empty() && stem.bytes().all(|b| b.is_ascii_digit()) {
+            Some(Self::Guest)
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn default_name(&self, file: &str) -> String {
+        let parts: Vec<&str> = file.split('/').collect();
+        let stem = parts[parts.len() - 1].trim_end_matches(".fw");
+        match self {
+            Self::Host => {
+                if parts.len() >= 3 && parts[parts.len() - 3] == "nodes" {
+                    format!("host-{}", parts[parts.len() - 2])
+                }
+                else {
+                    stem.to_string()
+                }
+            },
+            Self::VNet => format!("vnet-{stem}"),
+            _ => stem.to_string()
+        }
+    }
+
+    /// Scope prefix used when referencing IPSets from rules rendered into this kind of file.
+    pub fn get_scope(&self) -> &'static str {
+        match self {
+            Self::Cluster | Self::Host | Self::VNet => "dc",
+            Self::Guest => "guest"
+        }
+    }
+
+    /// Whether the PVE firewall accepts `[IPSET]` sections in this kind of file.
+    pub fn has_ipsets(&self) -> bool {
+        matches!(self, Self::Cluster | Self::Guest)
+    }
+
+    /// Whether the PVE firewall accepts rules of the given direction in this kind of file. VNet
+    /// firewalls only filter forwarded traffic, guests never see it.
+    pub fn allows(&self, direction: &Direction) -> bool {
+        match direction {
+            Direction::Forward => *self != Self::Guest,
+            Direction::In | Direction::Out => *self != Self::VNet
+        }
+    }
+}
+
+pub struct Target {
+    config: TargetConfig,
+    directory: String,
+    stat: OrigCache
+}
+
+impl Target {
+    pub fn new(config: TargetConfig, directory: String) -> Self {
+        let stat = OrigCache::new(
+            program_path(&config, &directory, ProgramPath::Static),
+            program_path(&config, &directory, ProgramPath::Original)
+        );
+        Self {
+            config,
+            directory,
+            stat
+        }
+    }
+
+    pub fn get_name(&self) -> String {
+        self.config.get_name()
+    }
+
+    pub fn get_kind(&self) -> TargetKind {
+        self.config.get_kind()
+    }
+
+    pub fn get_path(&self, path: ProgramPath) -> String {
+        program_path(&self.config, &self.directory, path)
+    }
+
+    pub fn try_update(&mut self) -> bool {
+        self.stat.try_update()
+    }
+
+    pub fn mark_as_updated(&mut self) {
+        self.stat.mark_as_updated();
+    }
+
+    /// IPSets rendered into this target, empty for kinds of files that cannot hold any.
+    pub fn get_ipsets(&self, store: &DomainStore, all_groups: &[Group], domain_files: &HashMap<String, Vec<String>>) -> Vec<IpSet> {
+        let kind = self.config.get_kind();
+        if !kind.has_ipsets() {
+            return Vec::new();
+        }
+
+        // Groups whose sets are the opposite endpoint of rules need their IPSets in this file too.
+        let referenced: Vec<String> = all_groups.iter()
+            .filter(|g| self.config.uses_group(&g.get_name()))
+            .flat_map(|g| g.get_referenced_groups())
+            .collect();
+        let ipset_groups: Vec<&Group> = all_groups.iter()
+            .filter(|g| self.config.uses_group(&g.get_name()) || referenced.contains(&g.get_name()))
+            .collect();
+
+        let mut fqdns: Vec<String> = Vec::new();
+        if self.config.get_groups().is_none() && self.config.get_domains().is_none() && kind == TargetKind::Cluster {
+            fqdns = store.get_fqdns();
+        }
+        else {
+            for group in &ipset_groups {
+                fqdns.extend(group.get_domains().iter().cloned());
+            }
+            for (name, domains) in domain_files {
+                if self.config.uses_domain_file(name) {
+                    fqdns.extend(domains.iter().cloned());
+                }
+            }
+        }
+
+        let mut ipsets = store.get_ipsets(&fqdns);
+        ipsets.extend(ipset_groups.iter().filter_map(|g| g.get_ipset(store)));
+        ipsets
+    }
+
+    pub fn render(&self, store: &DomainStore, all_groups: &[Group], domain_files: &HashMap<String, Vec<String>>) -> String {
+        let kind = self.config.get_kind();
+        let groups: Vec<&Group> = all_groups.iter().filter(|g| self.config.uses_group(&g.get_name())).collect();
+
+        let mut buf = self.stat.get_content().to_string();
+        buf += "\n# DYNAMIC CONTENT BEGIN\n\n\n";
+
+        for ipset in self.get_ipsets(store, all_groups, domain_files) {
+            buf += ipset.render().as_str();
+        }
+
+        let references: String = all_groups.iter()
+            .filter_map(|g| g.render_reference(&self.get_name()))
+            .collect();
+
+        if kind == TargetKind::Cluster {
+            if !references.is_empty() {
+                buf += format!("[RULES]\n\n{references}\n").as_str();
+            }
+            for group in &groups {
+                buf += group.render(store, &kind, all_groups).as_str();
+            }
+        }
+        else if !groups.is_empty() || !references.is_empty() {
+            buf += "[RULES]\n\n";
+            buf += references.as_str();
+            for group in &groups {
+                buf += format!("# group {}\n", group.get_name()).as_str();
+                buf += group.render_rules(store, &kind, all_groups).as_str();
+            }
+            buf += "\n";
+        }
+
+        buf += "\n# DYNAMIC CONTENT END\n\n\n";
+        buf
+    }
+}
+
+fn program_path(config: &TargetConfig, directory: &str, path: ProgramPath) -> String {
+    match path {
+        ProgramPath::Original => config.get_file(),
+        ProgramPath::Generated => format!("{directory}generated/{}.fw", config.get_name()),
+        ProgramPath::Static => format!("{directory}static/{}.fw", config.get_name())
+    }
+}